@@ -2,7 +2,6 @@ use actix_web::{ web, App,  HttpServer};
 use exchange_rs::get_orderbook::get_orderbook;
 use exchange_rs::processor::*;
 use exchange_rs::routes::*;
-use dotenvy;
 use exchange_rs::AppState;
 use exchange_rs::user_details::user_details;
 #[actix_web::main]
@@ -23,6 +22,23 @@ pub async fn main() -> std::io::Result<()> {
             .service(get_orderbook)
             .service(user_details)
             .service(create_market)
+            .service(resolve_market)
+            .service(cancel_order)
+            .service(amend_order)
+            .service(quote)
+            .service(create_stop_order)
+            .service(market_depth)
+            .service(market_ticker)
+            .service(open_orders)
+            .service(amm_trade)
+            .service(get_ledger)
+            .service(check_solvency)
+            .service(deposit)
+            .service(withdraw)
+            .service(get_withdrawals)
+            .service(pending_withdrawals)
+            .service(decide_withdrawal)
+            .service(portfolio)
     })
     .bind(bind_addr)?
     .run()
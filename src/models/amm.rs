@@ -0,0 +1,142 @@
+use crate::{ExchangeError, Ordertype, StockType, PAIR_PAYOUT};
+
+// counterparty name of trades against the market maker
+pub const AMM_USERNAME : &str = "market_maker";
+
+// Logarithmic market scoring rule (LMSR) market maker. Its cost function C(q) = b ln Σ exp(q_i / b),
+// in units of PAIR_PAYOUT, prices every outcome from the net shares q it has sold. Its worst case loss
+// is b ln N, which the market creator pays up front. Shares it sells are minted as full sets, the rest
+// of each set stays in its inventory, and full sets it ends up holding are merged back into cash, so
+// every share it trades is backed by the market's collateral like any other.
+#[derive(Debug)]
+pub struct Amm {
+    pub liquidity : f64,        // b, larger is deeper and moves prices less
+    pub sold : Vec<i64>,        // net shares of every outcome sold to users
+    pub inventory : Vec<u64>,   // shares of every outcome it holds
+    pub cash : u64              // subsidy plus proceeds not tied up in sets
+}
+
+impl Amm {
+    // funding needed to cover the worst case loss, rounded up
+    pub fn subsidy(liquidity : u64 , outcomes : usize) -> u64 {
+        (PAIR_PAYOUT as f64 * liquidity as f64 * (outcomes as f64).ln()).ceil() as u64
+    }
+    pub fn new(liquidity : u64 , outcomes : usize) -> Self {
+        Amm {
+            liquidity : liquidity as f64,
+            sold : vec![0; outcomes],
+            inventory : vec![0; outcomes],
+            cash : Self::subsidy(liquidity, outcomes)
+        }
+    }
+    // C(q) after `delta` more shares of `stock_type` are sold, computed as a stable log-sum-exp. The
+    // quantities are added as floats, so pricing any quantity can not wrap.
+    fn cost_with(&self , stock_type : &StockType , delta : f64) -> f64 {
+        let exponents : Vec<f64> = self.sold.iter().enumerate()
+            .map(|(index, &sold)| (sold as f64 + if index == stock_type.0 { delta } else { 0.0 }) / self.liquidity)
+            .collect();
+        let max = exponents.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let sum : f64 = exponents.iter().map(|exponent| (exponent - max).exp()).sum();
+        PAIR_PAYOUT as f64 * self.liquidity * (max + sum.ln())
+    }
+    // marginal price of `stock_type`, the prices of all outcomes add up to PAIR_PAYOUT
+    pub fn price(&self , stock_type : &StockType) -> f64 {
+        let exponent = |sold : i64| sold as f64 / self.liquidity;
+        let max = self.sold.iter().map(|&sold| exponent(sold)).fold(f64::NEG_INFINITY, f64::max);
+        let sum : f64 = self.sold.iter().map(|&sold| (exponent(sold) - max).exp()).sum();
+        PAIR_PAYOUT as f64 * (exponent(self.sold[stock_type.0]) - max).exp() / sum
+    }
+    // what buying `quantity` shares costs, rounded up in the market maker's favour and capped at u64::MAX
+    pub fn buy_cost(&self , stock_type : &StockType , quantity : u64) -> u64 {
+        (self.cost_with(stock_type, quantity as f64) - self.cost_with(stock_type, 0.0)).ceil() as u64
+    }
+    // what selling `quantity` shares pays, rounded down in the market maker's favour
+    pub fn sell_proceeds(&self , stock_type : &StockType , quantity : u64) -> u64 {
+        (self.cost_with(stock_type, 0.0) - self.cost_with(stock_type, -(quantity as f64))).floor().max(0.0) as u64
+    }
+    // Number of shares, at most `max`, that can be traded with the market maker after `traded` shares
+    // already have been, while every single share is priced at `price` or better. With `inclusive`
+    // false a share priced exactly at `price` is left to the order book.
+    pub fn tradable_within(&self , stock_type : &StockType , ordertype : &Ordertype , traded : u64 , max : u64 , price : u64 , inclusive : bool) -> u64 {
+        let acceptable = |unit : u64| {
+            let unit = unit as f64;
+            let unit_price = match ordertype {
+                Ordertype::Buy => self.cost_with(stock_type, unit) - self.cost_with(stock_type, unit - 1.0),
+                Ordertype::Sell => self.cost_with(stock_type, 1.0 - unit) - self.cost_with(stock_type, -unit),
+            };
+            match (ordertype, inclusive) {
+                (Ordertype::Buy, true) => unit_price <= price as f64,
+                (Ordertype::Buy, false) => unit_price < price as f64,
+                (Ordertype::Sell, true) => unit_price >= price as f64,
+                (Ordertype::Sell, false) => unit_price > price as f64,
+            }
+        };
+        // unit prices only get worse, so binary search the last acceptable share
+        let (mut low, mut high) = (0, max);
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            if acceptable(traded + mid) {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        low
+    }
+    // most shares, at most `max`, that a buyer can take after `traded` shares for `budget`
+    pub fn affordable(&self , stock_type : &StockType , traded : u64 , max : u64 , budget : u64) -> u64 {
+        let spent = self.buy_cost(stock_type, traded);
+        let (mut low, mut high) = (0, max);
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            if self.buy_cost(stock_type, traded + mid) - spent <= budget {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        low
+    }
+    // Sells `quantity` shares to a user and returns what they cost. Missing shares are minted as full
+    // sets out of the market maker's cash, which `collateral` then holds.
+    pub fn buy(&mut self , stock_type : &StockType , quantity : u64 , collateral : &mut u64) -> Result<u64, ExchangeError> {
+        let too_large = || Self::too_large(quantity);
+        let sold = i64::try_from(quantity).ok().and_then(|quantity| self.sold[stock_type.0].checked_add(quantity)).ok_or_else(too_large)?;
+        let cost = self.buy_cost(stock_type, quantity);
+        let missing = quantity.saturating_sub(self.inventory[stock_type.0]);
+        let minted = missing.checked_mul(PAIR_PAYOUT).ok_or_else(too_large)?;
+        let funds = self.cash.checked_add(cost).ok_or_else(too_large)?;
+        if funds < minted {
+            return Err(ExchangeError::MarketMakerOutOfLiquidity);
+        }
+        let mut inventory : Vec<u64> = self.inventory.iter().map(|held| held.checked_add(missing)).collect::<Option<_>>().ok_or_else(too_large)?;
+        *collateral = collateral.checked_add(minted).ok_or_else(too_large)?;
+        inventory[stock_type.0] -= quantity;
+        self.inventory = inventory;
+        self.cash = funds - minted;
+        self.sold[stock_type.0] = sold;
+        Ok(cost)
+    }
+    // Buys `quantity` shares from a user and returns what they pay. Full sets it then holds are merged.
+    pub fn sell(&mut self , stock_type : &StockType , quantity : u64 , collateral : &mut u64) -> Result<u64, ExchangeError> {
+        let too_large = || Self::too_large(quantity);
+        let sold = i64::try_from(quantity).ok().and_then(|quantity| self.sold[stock_type.0].checked_sub(quantity)).ok_or_else(too_large)?;
+        let proceeds = self.sell_proceeds(stock_type, quantity);
+        let mut inventory = self.inventory.clone();
+        inventory[stock_type.0] = inventory[stock_type.0].checked_add(quantity).ok_or_else(too_large)?;
+        let sets = inventory.iter().copied().min().unwrap_or(0);
+        let merged = sets.checked_mul(PAIR_PAYOUT).ok_or_else(too_large)?;
+        let funds = self.cash.checked_add(merged).ok_or_else(too_large)?;
+        if funds < proceeds {
+            return Err(ExchangeError::MarketMakerOutOfLiquidity);
+        }
+        self.inventory = inventory.into_iter().map(|held| held - sets).collect();
+        self.sold[stock_type.0] = sold;
+        self.cash = funds - proceeds;
+        *collateral -= merged;     // the merged sets were backed by the collateral
+        Ok(proceeds)
+    }
+    fn too_large(quantity : u64) -> ExchangeError {
+        ExchangeError::Validation(format!("Quantity {} is too large to trade with the market maker", quantity))
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt;
+
+use actix_web::{HttpResponse, ResponseError, http::StatusCode};
+use serde_json::{Value, json};
+
+// Every error the exchange reports. Routes turn it into a JSON body with a stable `code`, a
+// human readable `message` and variant specific `details`, e.g.
+// {"code": "INSUFFICIENT_FUNDS", "message": "...", "details": {"required": 500, "available": 200}}
+#[derive(Debug , Clone , PartialEq)]
+pub enum ExchangeError {
+    UserNotFound,
+    MarketNotFound,
+    OrderNotFound,
+    UsernameTaken,
+    UsernameReserved,
+    MarketAlreadyExists,
+    MarketSettled,
+    InvalidCredentials,
+    Unauthorized(String),       // missing, malformed or expired token
+    NotMarketCreator,
+    NotAdmin,
+    WithdrawalNotFound,
+    WithdrawalNotPending,       // the withdrawal was already approved or rejected
+    FundingFailed(String),      // the funding source declined or failed the transfer
+    FundingUnavailable,         // no funding source is configured
+    InsufficientFunds { required : u64 , available : u64 },
+    InsufficientStock { required : u64 , available : u64 },
+    UnknownOutcome { outcome : usize , outcomes : usize },
+    PostOnlyWouldTrade,
+    StopWouldTrigger,
+    NoMarketMaker,
+    MarketMakerOutOfLiquidity,
+    LimitExceeded { amount : u64 , limit : u64 },  // a trade with the market maker is worse than its limit
+    Validation(String),         // malformed order or market parameters
+    WorkerUnavailable           // the background worker crashed or did not answer
+}
+
+impl ExchangeError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ExchangeError::UserNotFound => "USER_NOT_FOUND",
+            ExchangeError::MarketNotFound => "MARKET_NOT_FOUND",
+            ExchangeError::OrderNotFound => "ORDER_NOT_FOUND",
+            ExchangeError::UsernameTaken => "USERNAME_TAKEN",
+            ExchangeError::UsernameReserved => "USERNAME_RESERVED",
+            ExchangeError::MarketAlreadyExists => "MARKET_ALREADY_EXISTS",
+            ExchangeError::MarketSettled => "MARKET_SETTLED",
+            ExchangeError::InvalidCredentials => "INVALID_CREDENTIALS",
+            ExchangeError::Unauthorized(_) => "UNAUTHORIZED",
+            ExchangeError::NotMarketCreator => "NOT_MARKET_CREATOR",
+            ExchangeError::NotAdmin => "NOT_ADMIN",
+            ExchangeError::WithdrawalNotFound => "WITHDRAWAL_NOT_FOUND",
+            ExchangeError::WithdrawalNotPending => "WITHDRAWAL_NOT_PENDING",
+            ExchangeError::FundingFailed(_) => "FUNDING_FAILED",
+            ExchangeError::FundingUnavailable => "FUNDING_UNAVAILABLE",
+            ExchangeError::InsufficientFunds { .. } => "INSUFFICIENT_FUNDS",
+            ExchangeError::InsufficientStock { .. } => "INSUFFICIENT_STOCK",
+            ExchangeError::UnknownOutcome { .. } => "UNKNOWN_OUTCOME",
+            ExchangeError::PostOnlyWouldTrade => "POST_ONLY_WOULD_TRADE",
+            ExchangeError::StopWouldTrigger => "STOP_WOULD_TRIGGER",
+            ExchangeError::NoMarketMaker => "NO_MARKET_MAKER",
+            ExchangeError::MarketMakerOutOfLiquidity => "MARKET_MAKER_OUT_OF_LIQUIDITY",
+            ExchangeError::LimitExceeded { .. } => "LIMIT_EXCEEDED",
+            ExchangeError::Validation(_) => "VALIDATION_FAILED",
+            ExchangeError::WorkerUnavailable => "WORKER_UNAVAILABLE",
+        }
+    }
+    pub fn details(&self) -> Value {
+        match self {
+            ExchangeError::InsufficientFunds { required, available } | ExchangeError::InsufficientStock { required, available } => {
+                json!({ "required" : required, "available" : available })
+            }
+            ExchangeError::UnknownOutcome { outcome, outcomes } => json!({ "outcome" : outcome, "outcomes" : outcomes }),
+            ExchangeError::LimitExceeded { amount, limit } => json!({ "amount" : amount, "limit" : limit }),
+            _ => Value::Null,
+        }
+    }
+}
+
+impl fmt::Display for ExchangeError {
+    fn fmt(&self , f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExchangeError::UserNotFound => write!(f, "User not found"),
+            ExchangeError::MarketNotFound => write!(f, "Market does not exist"),
+            ExchangeError::OrderNotFound => write!(f, "Order not found"),
+            ExchangeError::UsernameTaken => write!(f, "Username already exists, use a different username"),
+            ExchangeError::UsernameReserved => write!(f, "Username is reserved"),
+            ExchangeError::MarketAlreadyExists => write!(f, "Market already exists"),
+            ExchangeError::MarketSettled => write!(f, "Market is already settled"),
+            ExchangeError::InvalidCredentials => write!(f, "Invalid password"),
+            ExchangeError::Unauthorized(reason) => write!(f, "{}", reason),
+            ExchangeError::NotMarketCreator => write!(f, "Only the market creator can resolve this market"),
+            ExchangeError::NotAdmin => write!(f, "Only an admin can do this"),
+            ExchangeError::WithdrawalNotFound => write!(f, "Withdrawal not found"),
+            ExchangeError::WithdrawalNotPending => write!(f, "Withdrawal was already decided"),
+            ExchangeError::FundingFailed(reason) => write!(f, "Funding failed: {}", reason),
+            ExchangeError::FundingUnavailable => write!(f, "No funding source is configured"),
+            ExchangeError::InsufficientFunds { required, available } => write!(f, "Insufficient funds. Required: {}, Available: {}", required, available),
+            ExchangeError::InsufficientStock { required, available } => write!(f, "Insufficient stock. Required: {}, Available: {}", required, available),
+            ExchangeError::UnknownOutcome { outcome, outcomes } => write!(f, "Unknown outcome {}, market has {} outcomes", outcome, outcomes),
+            ExchangeError::PostOnlyWouldTrade => write!(f, "Post-only order would trade against the book"),
+            ExchangeError::StopWouldTrigger => write!(f, "Stop order would trigger immediately"),
+            ExchangeError::NoMarketMaker => write!(f, "Market has no market maker"),
+            ExchangeError::MarketMakerOutOfLiquidity => write!(f, "Market maker is out of liquidity"),
+            ExchangeError::LimitExceeded { amount, limit } => write!(f, "Trade amount {} is outside the limit {}", amount, limit),
+            ExchangeError::Validation(message) => write!(f, "{}", message),
+            ExchangeError::WorkerUnavailable => write!(f, "Background worker crashed"),
+        }
+    }
+}
+
+impl std::error::Error for ExchangeError {}
+
+impl ResponseError for ExchangeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ExchangeError::UserNotFound | ExchangeError::MarketNotFound | ExchangeError::OrderNotFound
+                | ExchangeError::WithdrawalNotFound => StatusCode::NOT_FOUND,
+            ExchangeError::UsernameTaken | ExchangeError::UsernameReserved | ExchangeError::MarketAlreadyExists
+                | ExchangeError::MarketSettled | ExchangeError::PostOnlyWouldTrade | ExchangeError::StopWouldTrigger
+                | ExchangeError::MarketMakerOutOfLiquidity | ExchangeError::WithdrawalNotPending => StatusCode::CONFLICT,
+            ExchangeError::InvalidCredentials | ExchangeError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ExchangeError::NotMarketCreator | ExchangeError::NotAdmin => StatusCode::FORBIDDEN,
+            ExchangeError::InsufficientFunds { .. } | ExchangeError::InsufficientStock { .. } | ExchangeError::UnknownOutcome { .. }
+                | ExchangeError::NoMarketMaker | ExchangeError::LimitExceeded { .. } | ExchangeError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ExchangeError::FundingFailed(_) => StatusCode::BAD_GATEWAY,
+            ExchangeError::FundingUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ExchangeError::WorkerUnavailable => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({
+            "code" : self.code(),
+            "message" : self.to_string(),
+            "details" : self.details()
+        }))
+    }
+}
@@ -0,0 +1,70 @@
+use chrono::{DateTime, Utc};
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+
+use crate::ExchangeError;
+
+// Where money enters and leaves the exchange, e.g. a bank or card processor. Both calls move the
+// money outside the exchange and return the processor's reference for the transfer; the worker
+// books the exchange side only once they succeed.
+pub trait FundingSource {
+    // pulls `amount` from the user's external account
+    fn deposit(&mut self , username : &str , amount : u64) -> Result<String, ExchangeError>;
+    // pays `amount` out to the user's external account
+    fn withdraw(&mut self , username : &str , amount : u64) -> Result<String, ExchangeError>;
+}
+
+// Local stand-in for a payment processor that settles every transfer at once. Transfers above
+// `limit` are declined, like a card limit.
+#[derive(Debug , Default , Clone)]
+pub struct MockFunding {
+    pub limit : Option<u64>
+}
+impl MockFunding {
+    pub fn with_limit(limit : u64) -> Self {
+        MockFunding { limit : Some(limit) }
+    }
+    fn transfer(&self , amount : u64) -> Result<String, ExchangeError> {
+        match self.limit {
+            Some(limit) if amount > limit => Err(ExchangeError::FundingFailed(format!("Transfer of {} is above the limit of {}", amount, limit))),
+            _ => Ok(format!("mock_{}", nanoid!())),
+        }
+    }
+}
+impl FundingSource for MockFunding {
+    fn deposit(&mut self , _username : &str , amount : u64) -> Result<String, ExchangeError> {
+        self.transfer(amount)
+    }
+    fn withdraw(&mut self , _username : &str , amount : u64) -> Result<String, ExchangeError> {
+        self.transfer(amount)
+    }
+}
+
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct Deposit {
+    pub reference : String,     // the funding source's id of the transfer
+    pub amount : u64,
+    pub balance : u64           // available after the deposit
+}
+
+#[derive(Debug , Clone , Copy , PartialEq , Serialize , Deserialize)]
+pub enum WithdrawalStatus {
+    Pending,        // the amount is held until an admin decides
+    Approved,       // paid out through the funding source
+    Rejected        // the amount went back to the user
+}
+#[derive(Debug , Clone , Copy , PartialEq , Deserialize)]
+pub enum WithdrawalDecision {
+    Approve,
+    Reject
+}
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct Withdrawal {
+    pub withdrawal_id : String,
+    pub username : String,
+    pub amount : u64,
+    pub status : WithdrawalStatus,
+    pub requested_at : DateTime<Utc>,
+    pub decided_at : Option<DateTime<Utc>>,
+    pub reference : Option<String>      // the funding source's id of the payout, once approved
+}
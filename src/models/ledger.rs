@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::StockType;
+
+// Holder of an amount in the ledger. Users' accounts hold cash and the shares of every market they
+// trade, markets' accounts only their own.
+#[derive(Debug , Clone , PartialEq , Eq , Hash , Serialize)]
+pub enum Account {
+    Available(String),      // a user's free cash or shares
+    Reserved(String),       // a user's cash or shares locked by open orders
+    PendingWithdrawal(String),  // a user's cash held by withdrawals waiting for approval
+    Collateral(String),     // a market's pool backing every full set of its outcomes
+    MarketMaker(String),    // a market's market maker, its cash and inventory
+    Issuance(String),       // source of a market's shares when sets are minted, and their sink when burned
+    Funding                 // money entering or leaving the exchange, e.g. deposits and the signup credit
+}
+#[derive(Debug , Clone , PartialEq , Eq , Hash , Serialize)]
+pub enum Asset {
+    Cash,
+    Shares { market_id : String , stock_type : StockType }
+}
+impl Asset {
+    pub fn shares(market_id : &str , stock_type : &StockType) -> Self {
+        Asset::Shares { market_id : market_id.to_string(), stock_type : stock_type.clone() }
+    }
+}
+// why an amount moved
+#[derive(Debug , Clone , Copy , PartialEq , Eq , Serialize)]
+pub enum Reason {
+    SignupCredit,
+    Deposit,
+    WithdrawalRequested,    // a withdrawal holds its amount until it is decided
+    WithdrawalApproved,
+    WithdrawalRejected,
+    MarketSubsidy,          // a market creator funds its market maker
+    Split,
+    Merge,
+    OrderReserved,          // an order locks cash or shares
+    OrderReleased,          // an order gives back what it no longer needs, e.g. when cancelled
+    Trade,
+    PriceImprovement,       // a buy filled below its limit price gets the difference back
+    AmmTrade,
+    AmmMint,                // the market maker mints the sets it sells from
+    AmmMerge,               // the market maker merges the full sets it bought back
+    Settlement,             // winning shares are paid out and every share is retired
+    MarketMakerReturn       // a settled market maker hands what it holds back to the creator
+}
+// what a movement belongs to
+#[derive(Debug , Clone , Default , PartialEq , Serialize)]
+pub struct Reference {
+    pub market_id : Option<String>,
+    pub order_id : Option<String>,
+    pub trade_id : Option<String>,
+    pub funding_id : Option<String>     // a deposit's transfer or a withdrawal
+}
+impl Reference {
+    pub fn market(market_id : &str) -> Self {
+        Reference { market_id : Some(market_id.to_string()), ..Default::default() }
+    }
+    pub fn order(market_id : &str , order_id : &str) -> Self {
+        Reference { order_id : Some(order_id.to_string()), ..Self::market(market_id) }
+    }
+    pub fn funding(funding_id : &str) -> Self {
+        Reference { funding_id : Some(funding_id.to_string()), ..Default::default() }
+    }
+    pub fn trade(market_id : &str , order_id : Option<&str> , trade_id : &str) -> Self {
+        Reference { order_id : order_id.map(str::to_string), trade_id : Some(trade_id.to_string()), ..Self::market(market_id) }
+    }
+}
+// One movement of `amount` of `asset` from the credited to the debited account. Every entry balances
+// by construction, movements that touch more accounts are posted as several entries with the same
+// reference.
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct JournalEntry {
+    pub entry_id : u64,
+    pub timestamp : DateTime<Utc>,
+    pub debit : Account,    // receives the amount
+    pub credit : Account,   // gives the amount
+    pub asset : Asset,
+    pub amount : u64,
+    pub reason : Reason,
+    pub reference : Reference
+}
+impl JournalEntry {
+    pub fn touches_user(&self , username : &str) -> bool {
+        [&self.debit, &self.credit].into_iter().any(|account| match account {
+            Account::Available(name) | Account::Reserved(name) | Account::PendingWithdrawal(name) => name == username,
+            _ => false,
+        })
+    }
+}
+// append only journal of every cash and share movement on the exchange
+#[derive(Debug , Default)]
+pub struct Ledger {
+    pub entries : Vec<JournalEntry>
+}
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn post(&mut self , debit : Account , credit : Account , asset : Asset , amount : u64 , reason : Reason , reference : Reference) {
+        if amount == 0 {
+            return;
+        }
+        self.entries.push(JournalEntry {
+            entry_id : self.entries.len() as u64 + 1,
+            timestamp : Utc::now(),
+            debit,
+            credit,
+            asset,
+            amount,
+            reason,
+            reference
+        });
+    }
+    pub fn entries_of(&self , username : &str) -> Vec<JournalEntry> {
+        self.entries.iter().filter(|entry| entry.touches_user(username)).cloned().collect()
+    }
+    // debits minus credits of every account and asset that was ever posted to
+    pub fn balances(&self) -> HashMap<(Account, Asset), i128> {
+        let mut balances = HashMap::new();
+        for entry in &self.entries {
+            *balances.entry((entry.debit.clone(), entry.asset.clone())).or_default() += entry.amount as i128;
+            *balances.entry((entry.credit.clone(), entry.asset.clone())).or_default() -= entry.amount as i128;
+        }
+        balances
+    }
+    // debits minus credits of an account in one asset
+    pub fn balance(&self , account : &Account , asset : &Asset) -> i128 {
+        self.entries.iter().filter(|entry| entry.asset == *asset).map(|entry| {
+            let debit = if entry.debit == *account { entry.amount as i128 } else { 0 };
+            let credit = if entry.credit == *account { entry.amount as i128 } else { 0 };
+            debit - credit
+        }).sum()
+    }
+}
@@ -1,11 +1,50 @@
-use crate::{ User, order::*};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::{ Account, Amm, AMM_USERNAME, Asset, ExchangeError, Ledger, OutcomeTicker, PortfolioPosition, Position, Reason, Reference, User, order::*};
+use chrono::{DateTime, Utc};
 use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+
+// Collateral locked for every full set of outcomes, which is what the winning share pays out. Prices are
+// quoted in the same unit, so in a binary market a buy of A at p and a buy of B at PAIR_PAYOUT - p
+// together fund a new pair.
+pub const PAIR_PAYOUT : u64 = 100;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "WinningOutcomeRepr")]
 pub enum WinningOutcome {
+    Outcome(usize), // index of the winning outcome, each of its shares pays PAIR_PAYOUT
+    Neither, // Draw or invalid outcome - every outcome pays an equal share of PAIR_PAYOUT
+}
+#[allow(non_upper_case_globals)]
+impl WinningOutcome {
+    pub const OutcomeA : WinningOutcome = WinningOutcome::Outcome(0);
+    pub const OutcomeB : WinningOutcome = WinningOutcome::Outcome(1);
+}
+// accepts an outcome index as well as the binary names
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WinningOutcomeRepr {
+    Index(usize),
+    Named(NamedOutcome)
+}
+#[derive(Deserialize)]
+enum NamedOutcome {
+    Outcome(usize),
     OutcomeA,
     OutcomeB,
-    Neither, // Draw or invalid outcome - both tokens get 50% payout
+    Neither
+}
+impl From<WinningOutcomeRepr> for WinningOutcome {
+    fn from(repr : WinningOutcomeRepr) -> Self {
+        match repr {
+            WinningOutcomeRepr::Index(index) | WinningOutcomeRepr::Named(NamedOutcome::Outcome(index)) => WinningOutcome::Outcome(index),
+            WinningOutcomeRepr::Named(NamedOutcome::OutcomeA) => WinningOutcome::OutcomeA,
+            WinningOutcomeRepr::Named(NamedOutcome::OutcomeB) => WinningOutcome::OutcomeB,
+            WinningOutcomeRepr::Named(NamedOutcome::Neither) => WinningOutcome::Neither,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -13,76 +52,806 @@ pub struct Market {
     pub market_id : String,
     pub created_by : String,
     pub market_name : String,
-    pub stock_a: OrderBook,
-    pub stock_b: OrderBook,
+    pub outcomes : Vec<String>,     // outcome names, indexed by StockType
+    pub books : Vec<OrderBook>,     // one order book per outcome
     pub trades : Vec<Trade>,
     pub winning_outcome : Option<WinningOutcome>,
-    pub is_settled : bool
+    pub is_settled : bool,
+    pub collateral : u64,   // collateral backing all outstanding pairs
+    pub tick_size : u64,    // prices must be a multiple of this
+    pub stop_orders : Vec<StopOrder>,   // pending triggers, oldest first
+    pub amm : Option<Amm>,              // market maker funded by the creator, if any
+    pub expiries : BTreeMap<DateTime<Utc>, Vec<(String, String)>>  // expiry → (order_id, username) of resting good-till-date orders
+}
+
+// where the best liquidity for an incoming order rests, by price level in that book
+#[derive(Clone, Copy)]
+enum Counter {
+    Direct(u64),        // opposite side of the order's own book
+    Complement(u64)     // same side of the complementary outcome's book
 }
 
 impl Market {
-    pub fn initialise_market (market_name : String , username : String) -> Self{ 
-        let market = Market{
+    pub fn initialise_market (market_name : String , username : String , outcomes : Vec<String> , tick_size : u64) -> Self{ 
+        Market{
             market_id : nanoid!(),
             created_by : username,
             market_name ,
-            stock_a : OrderBook::new(),
-            stock_b : OrderBook::new(),
+            books : outcomes.iter().map(|_| OrderBook::new()).collect(),
+            outcomes,
             trades : vec![],
             winning_outcome : None,
-            is_settled : false
+            is_settled : false,
+            collateral : 0,
+            tick_size,
+            stop_orders : vec![],
+            amm : None,
+            expiries : BTreeMap::new()
+        }
+    }
+    // a share can never be worth nothing or the full payout, so prices lie strictly between both
+    pub fn validate_price(&self , price : u64) -> Result<(), ExchangeError> {
+        if price == 0 || price >= PAIR_PAYOUT {
+            return Err(ExchangeError::Validation(format!("Price must be between 0 and {} (exclusive)", PAIR_PAYOUT)));
+        }
+        if !price.is_multiple_of(self.tick_size) {
+            return Err(ExchangeError::Validation(format!("Price must be a multiple of the tick size {}", self.tick_size)));
+        }
+        Ok(())
+    }
+    // an empty order would neither match nor rest, and leave its reservation behind
+    pub fn validate_quantity(&self , quantity : u64) -> Result<(), ExchangeError> {
+        if quantity == 0 {
+            return Err(ExchangeError::Validation("Quantity must be greater than zero".to_string()));
+        }
+        Ok(())
+    }
+    pub fn validate_outcome(&self , stock_type : &StockType) -> Result<(), ExchangeError> {
+        if stock_type.0 >= self.outcomes.len() {
+            return Err(ExchangeError::UnknownOutcome { outcome : stock_type.0, outcomes : self.outcomes.len() });
+        }
+        Ok(())
+    }
+    // only a binary market has a complement to mint or burn against, with more outcomes a full set is
+    // needed for that, which split and merge provide
+    pub fn is_binary(&self) -> bool {
+        self.outcomes.len() == 2
+    }
+    pub fn book(&self , stock_type : &StockType) -> &OrderBook {
+        &self.books[stock_type.0]
+    }
+    pub fn book_mut(&mut self , stock_type : &StockType) -> &mut OrderBook {
+        &mut self.books[stock_type.0]
+    }
+    fn remove_resting(&mut self , order_id : &str , username : &str) -> Option<Order> {
+        self.books.iter_mut().find_map(|book| book.remove_order(order_id, username))
+    }
+    // Best price an incoming order can trade at right now and where that liquidity rests. Besides the
+    // opposite side of its own book, a buy can mint against a buy of the complement (an ask at
+    // PAIR_PAYOUT - bid) and a sell can burn against a sell of the complement (a bid at PAIR_PAYOUT - ask).
+    // On equal prices the order's own book goes first. Only binary markets have a complement.
+    fn best_counter(&self , stock_type : &StockType , ordertype : &Ordertype) -> Option<(Counter, u64)> {
+        let book = self.book(stock_type);
+        let complement = self.is_binary().then(|| self.book(&stock_type.complement()));
+        match ordertype {
+            Ordertype::Buy => {
+                let direct = book.best_ask().map(|ask| (Counter::Direct(ask), ask));
+                let minted = complement.and_then(|complement| complement.buy.range(..=PAIR_PAYOUT).next_back())
+                    .map(|(&bid, _)| (Counter::Complement(bid), PAIR_PAYOUT - bid));
+                match (direct, minted) {
+                    (Some(direct), Some(minted)) => Some(if minted.1 < direct.1 { minted } else { direct }),
+                    (direct, minted) => direct.or(minted),
+                }
+            }
+            Ordertype::Sell => {
+                let direct = book.best_bid().map(|bid| (Counter::Direct(bid), bid));
+                let burned = complement.and_then(|complement| complement.sell.range(..=PAIR_PAYOUT).next())
+                    .map(|(&ask, _)| (Counter::Complement(ask), PAIR_PAYOUT - ask));
+                match (direct, burned) {
+                    (Some(direct), Some(burned)) => Some(if burned.1 > direct.1 { burned } else { direct }),
+                    (direct, burned) => direct.or(burned),
+                }
+            }
+        }
+    }
+    pub fn best_counter_price(&self , stock_type : &StockType , ordertype : &Ordertype) -> Option<u64> {
+        self.best_counter(stock_type, ordertype).map(|(_, price)| price)
+    }
+    // every price level an incoming order could trade with, best first, priced from the order's point of
+    // view. Icebergs count with their hidden reserve, which replenishes within the same level.
+    fn counter_levels(&self , stock_type : &StockType , ordertype : &Ordertype) -> Vec<(u64, &VecDeque<Order>)> {
+        let book = self.book(stock_type);
+        let complement = self.is_binary().then(|| self.book(&stock_type.complement()));
+        let mut levels : Vec<(u64, &VecDeque<Order>)> = match ordertype {
+            Ordertype::Buy => book.sell.iter().map(|(&ask, queue)| (ask, queue))
+                .chain(complement.into_iter().flat_map(|complement| complement.buy.range(..=PAIR_PAYOUT)).map(|(&bid, queue)| (PAIR_PAYOUT - bid, queue)))
+                .collect(),
+            Ordertype::Sell => book.buy.iter().map(|(&bid, queue)| (bid, queue))
+                .chain(complement.into_iter().flat_map(|complement| complement.sell.range(..=PAIR_PAYOUT)).map(|(&ask, queue)| (PAIR_PAYOUT - ask, queue)))
+                .collect(),
         };
-        market
+        // stable sort keeps the order's own book ahead on equal prices
+        match ordertype {
+            Ordertype::Buy => levels.sort_by_key(|&(price, _)| price),
+            Ordertype::Sell => levels.sort_by_key(|&(price, _)| Reverse(price)),
+        }
+        levels
+    }
+    // quantity of `order` that would trade right now, taking its self-trade prevention into account
+    pub fn fillable_quantity(&self , order : &Order) -> u64 {
+        let mut remaining = order.quantity;
+        let mut filled = 0;
+        for (price, queue) in self.counter_levels(&order.stock_type, &order.ordertype) {
+            let crosses = match order.ordertype {
+                Ordertype::Buy => price <= order.price,
+                Ordertype::Sell => price >= order.price,
+            };
+            if !crosses {
+                break;
+            }
+            for resting in queue {
+                if remaining == 0 {
+                    return filled;
+                }
+                if resting.username == order.username {
+                    let (cancelled, _) = order.self_trade_prevention.cancelled_quantities(remaining, resting.total_quantity());
+                    remaining -= cancelled;
+                } else {
+                    let trade_qty = remaining.min(resting.total_quantity());
+                    remaining -= trade_qty;
+                    filled += trade_qty;
+                }
+            }
+        }
+        filled
+    }
+    // Matches an order against both books until it is filled or nothing crosses its price anymore.
+    // Limit orders have their funds or stock locked per share up front; market buys have the cost of
+    // their fill plan `reserved` instead, pay each fill out of it and stop once it runs out. Resting
+    // orders of the same user never trade, they are handled by the order's self-trade prevention and
+    // whatever is cancelled that way is released back to the user.
+    pub fn match_order(&mut self , order : &mut Order , user : &mut User , ledger : &mut Ledger , mut reserved : Option<&mut u64>) -> OrderFill {
+        let pays_per_fill = reserved.is_some();
+        let mut trades = vec![];
+        let mut cancelled = 0;
+        while order.quantity > 0 {
+            let Some((counter, price)) = self.best_counter(&order.stock_type, &order.ordertype) else {
+                break;  // nothing left to match against
+            };
+            let crosses = match order.ordertype {
+                Ordertype::Buy => price <= order.price,
+                Ordertype::Sell => price >= order.price,
+            };
+            if !crosses {
+                break;
+            }
+            let (stock_type, side, level, kind) = match (counter, &order.ordertype) {
+                (Counter::Direct(level), Ordertype::Buy) => (order.stock_type.clone(), Ordertype::Sell, level, TradeKind::Transfer),
+                (Counter::Direct(level), Ordertype::Sell) => (order.stock_type.clone(), Ordertype::Buy, level, TradeKind::Transfer),
+                (Counter::Complement(level), Ordertype::Buy) => (order.stock_type.complement(), Ordertype::Buy, level, TradeKind::Mint),
+                (Counter::Complement(level), Ordertype::Sell) => (order.stock_type.complement(), Ordertype::Sell, level, TradeKind::Burn),
+            };
+            let book = self.book_mut(&stock_type);
+            let Some(mut resting) = book.pop_front(&side, level) else {
+                continue;
+            };
+
+            if resting.username == order.username {
+                let (incoming, resting_cancelled) = order.self_trade_prevention.cancelled_quantities(order.quantity, resting.total_quantity());
+                order.quantity -= incoming;
+                if !pays_per_fill {
+                    order.release_quantity(incoming, user, ledger);
+                }
+                cancelled += incoming;
+                resting.reduce_quantity(resting_cancelled);
+                resting.release_quantity(resting_cancelled, user, ledger);
+                book.requeue(resting);
+                continue;
+            }
+
+            let mut trade_qty = order.quantity.min(resting.quantity);
+            if let Some(reserved) = reserved.as_deref_mut() {
+                trade_qty = trade_qty.min(reserved.checked_div(price).unwrap_or(trade_qty));
+                if trade_qty == 0 {
+                    book.push_front(resting);
+                    break;      // reservation used up
+                }
+                *reserved -= trade_qty * price;
+            }
+            order.quantity -= trade_qty;
+            resting.quantity -= trade_qty;
+            // the seller is always `from`, for mints and burns the resting order is the counterparty
+            let (from, to) = match order.ordertype {
+                Ordertype::Buy => (resting.username.clone(), order.username.clone()),
+                Ordertype::Sell => (order.username.clone(), resting.username.clone()),
+            };
+            let maker_order_id = resting.order_id.clone();
+            book.requeue(resting);
+            match kind {
+                TradeKind::Mint => self.collateral += PAIR_PAYOUT * trade_qty,
+                TradeKind::Burn => self.collateral -= PAIR_PAYOUT * trade_qty,
+                TradeKind::Transfer | TradeKind::Amm => {}
+            }
+            trades.push(Trade {
+                trade_id : nanoid!(),
+                market_id : self.market_id.clone(),
+                timestamp : Utc::now(),
+                from,
+                to,
+                trade_qty,
+                trade_price : price,
+                value : price * trade_qty,
+                stock_type : order.stock_type.clone(),
+                kind,
+                maker_order_id : Some(maker_order_id),
+                taker_order_id : Some(order.order_id.clone()),
+                aggressor : order.ordertype.clone()
+            });
+        }
+        self.trades.extend(trades.iter().cloned());
+        OrderFill { trades, cancelled }
+    }
+    pub fn add_limit_order(&mut self , mut order : Order , user : &mut User , ledger : &mut Ledger) -> Result<OrderFill , ExchangeError> {
+        self.validate_outcome(&order.stock_type)?;
+        self.validate_price(order.price)?;
+        self.validate_quantity(order.quantity)?;
+        if let TimeInForce::GoodTillDate(expiry) = order.time_in_force && expiry <= Utc::now() {
+            return Err(ExchangeError::Validation("Expiry time must be in the future".to_string()));
+        }
+        if order.post_only != PostOnly::Disabled {
+            if matches!(order.time_in_force, TimeInForce::ImmediateOrCancel | TimeInForce::FillOrKill) {
+                return Err(ExchangeError::Validation("Post-only orders can not be immediate-or-cancel or fill-or-kill".to_string()));
+            }
+            let best_opposite = self.best_counter_price(&order.stock_type, &order.ordertype);
+            order.price = order.post_only.price(&order.ordertype, order.price, best_opposite, self.tick_size)?;
+        }
+        // a fill-or-kill order that can not fully fill leaves the book and the user untouched
+        if order.time_in_force == TimeInForce::FillOrKill && self.fillable_quantity(&order) < order.quantity {
+            return Ok(OrderFill { trades : vec![], cancelled : order.quantity });
+        }
+        if order.display_quantity == Some(0) {
+            return Err(ExchangeError::Validation("Display quantity must be greater than zero".to_string()));
+        }
+        order.lock(user, ledger)?;    // Funds or stock locked immediately
+
+        let mut fill = self.match_order(&mut order, user, ledger, None);
+        if order.quantity > 0 {
+            match order.time_in_force {
+                TimeInForce::ImmediateOrCancel | TimeInForce::FillOrKill => {
+                    fill.cancelled += order.quantity;
+                    order.release(user, ledger);    // refund the unfilled remainder
+                }
+                TimeInForce::GoodTillCancel => {
+                    order.hide_reserve();
+                    self.book_mut(&order.stock_type).book_order(order);
+                }
+                TimeInForce::GoodTillDate(expiry) => {
+                    // remember when the unfilled part has to leave the book
+                    self.expiries.entry(expiry).or_default().push((order.order_id.clone(), order.username.clone()));
+                    order.hide_reserve();
+                    self.book_mut(&order.stock_type).book_order(order);
+                }
+            }
+        }
+        Ok(fill)
+    }
+    // removes good-till-date orders whose expiry has passed and releases what they locked
+    pub fn expire_orders(&mut self , now : DateTime<Utc> , users : &mut HashMap<String, User> , ledger : &mut Ledger) {
+        while let Some(entry) = self.expiries.first_entry() {
+            if *entry.key() > now {
+                break;
+            }
+            for (order_id, username) in entry.remove() {
+                let order = self.remove_resting(&order_id, &username);
+                // the order may already be filled or cancelled
+                if let (Some(order), Some(user)) = (order, users.get_mut(&username)) {
+                    order.release(user, ledger);
+                }
+            }
+        }
     }
-    pub fn add_limit_order(&mut self , mut order : Order , user : &mut User) -> Result<Vec<Trade> , String> {
-        match order.stock_type {
-            StockType::StockA => { 
-                let mut v  =  self.stock_a.add_limit_order(order, user);
-                match v {
-                    Ok(mut v) => {
-                       let v2 = v.clone();
-                       self.trades.append(&mut v);
-                       Ok(v2)
-                    }
-                    Err(err) => {
-                        Err(err)
-                    }
+    // A market order is an immediate-or-cancel order whose price limit is only the optional worst price
+    // in `limits`. The fill plan is computed first, so an all-or-nothing order that can not fully fill
+    // leaves the book and the user untouched. Sells then lock the stock and get the unfilled part back,
+    // buys reserve exactly the planned cost and pay every fill out of that reservation.
+    #[allow(clippy::too_many_arguments)]
+    pub fn execute_market_order(&mut self , order_id : String , username : String , ordertype : Ordertype , quantity : u64, stock_type : StockType , user : &mut User , ledger : &mut Ledger , market_id : String , self_trade_prevention : SelfTradePrevention , limits : SlippageLimits , all_or_nothing : bool)-> Result<OrderFill , ExchangeError> {
+        let price = self.market_order_price(&ordertype, limits)?;
+        let plan = self.quote_market_order(&username, ordertype.clone(), quantity, stock_type.clone(), user, self_trade_prevention, limits)?;
+        if all_or_nothing && plan.unfilled > 0 {
+            return Ok(OrderFill { trades : vec![], cancelled : quantity });
+        }
+        let mut order = Order {
+            order_id,
+            price,
+            quantity,
+            stock_type,
+            username,
+            timestamp : Utc::now(),
+            ordertype,
+            market_id,
+            time_in_force : TimeInForce::ImmediateOrCancel,
+            post_only : PostOnly::Disabled,
+            self_trade_prevention,
+            display_quantity : None,
+            hidden_quantity : 0,
+            original_quantity : quantity
+        };
+        // the market maker's share of the plan does not depend on the book, so it is traded first
+        let amm_trade = match plan.amm_filled {
+            0 => None,
+            amm_filled => {
+                // the market maker's side is only posted against the reservation made below
+                let source = Account::Reserved(order.username.clone());
+                Some(self.trade_with_amm(&order.username, ledger, source, Some(&order.order_id), &order.stock_type, &order.ordertype, amm_filled)?)
+            }
+        };
+        let mut fill = match order.ordertype {
+            Ordertype::Buy => {
+                // the plan never spends more than the balance
+                user.reserve(ledger, &order.order_id, &self.market_id, &order.stock_type, plan.total_cost, 0)?;
+                let mut reserved = plan.total_cost;
+                if let Some((cost, _)) = &amm_trade {
+                    reserved -= cost;
+                    user.consume(&order.order_id, *cost, 0);
+                    user.position_mut(&self.market_id, &order.stock_type).buy(plan.amm_filled, *cost);
+                    order.quantity -= plan.amm_filled;
+                    *user.holdings.entry(self.market_id.clone()).or_default().stock_mut(&order.stock_type) += plan.amm_filled;
                 }
+                let fill = self.match_order(&mut order, user, ledger, Some(&mut reserved));
+                user.release(ledger, Reason::OrderReleased, &order.order_id, reserved, 0);     // nothing is left unless the plan and the match disagree
+                fill
             }
-            StockType::StockB => {
-               let mut v  =  self.stock_b.add_limit_order(order, user );
-                match v {
-                    Ok(mut v) => {
-                       let v2 = v.clone();
-                       self.trades.append(&mut v);
-                       Ok(v2)
-                    }
-                    Err(err) => {
-                        Err(err)
-                    }
+            Ordertype::Sell => {
+                order.lock(user, ledger)?;
+                if let Some((proceeds, _)) = &amm_trade {
+                    order.quantity -= plan.amm_filled;     // the locked stock went to the market maker
+                    user.consume(&order.order_id, 0, plan.amm_filled);
+                    user.position_mut(&self.market_id, &order.stock_type).sell(plan.amm_filled, *proceeds);
+                    user.balance += proceeds;
                 }
+                let fill = self.match_order(&mut order, user, ledger, None);
+                order.release(user, ledger);
+                fill
+            }
+        };
+        if let Some((_, trade)) = amm_trade {
+            fill.trades.insert(0, trade);
+        }
+        fill.cancelled += order.quantity;
+        Ok(fill)
+    }
+    // worst price a market order may trade at given its slippage limits
+    fn market_order_price(&self , ordertype : &Ordertype , limits : SlippageLimits) -> Result<u64, ExchangeError> {
+        let price = match (ordertype, limits) {
+            (Ordertype::Buy, SlippageLimits { min_price : Some(_), .. }) => return Err(ExchangeError::Validation("min_price only applies to sell orders".to_string())),
+            (Ordertype::Sell, SlippageLimits { max_price : Some(_), .. } | SlippageLimits { max_total_cost : Some(_), .. }) => {
+                return Err(ExchangeError::Validation("max_price and max_total_cost only apply to buy orders".to_string()));
             }
+            (Ordertype::Buy, _) => limits.max_price.unwrap_or(u64::MAX),
+            (Ordertype::Sell, _) => limits.min_price.unwrap_or(0),
+        };
+        if let Some(limit) = limits.max_price.or(limits.min_price) {
+            self.validate_price(limit)?;
         }
-        
+        Ok(price)
     }
-    pub fn execute_market_order(&mut self , username : String , ordertype : Ordertype , quantity : u64, stock_type : StockType , user : &mut User , market_id : String  )-> Result<Vec<Trade> , String> {
-        let mut v = match stock_type {
-            StockType::StockA => {
-                self.stock_a.execute_market_order(username, ordertype, quantity , user , market_id , stock_type)
+    // Dry run of `execute_market_order`: walks the same levels with the same limits, funds and
+    // self-trade prevention and reports what would fill, without touching the books or the user.
+    #[allow(clippy::too_many_arguments)]
+    pub fn quote_market_order(&self , username : &str , ordertype : Ordertype , quantity : u64 , stock_type : StockType , user : &User , self_trade_prevention : SelfTradePrevention , limits : SlippageLimits) -> Result<Quote, ExchangeError> {
+        self.validate_outcome(&stock_type)?;
+        self.validate_quantity(quantity)?;
+        let limit_price = self.market_order_price(&ordertype, limits)?;
+        let mut budget = match ordertype {
+            Ordertype::Buy => user.balance.min(limits.max_total_cost.unwrap_or(u64::MAX)),
+            Ordertype::Sell => {
+                let available = user.holdings.get(&self.market_id).map_or(0, |holdings| holdings.stock(&stock_type));
+                if available < quantity {
+                    return Err(ExchangeError::InsufficientStock { required : quantity, available });
+                }
+                u64::MAX
             }
-            StockType::StockB => {
-                self.stock_b.execute_market_order(username, ordertype, quantity , user  , market_id , stock_type)
+        };
+        let mut quote = Quote::default();
+        let mut remaining = quantity;
+        'levels: for (price, queue) in self.counter_levels(&stock_type, &ordertype) {
+            let crosses = match ordertype {
+                Ordertype::Buy => price <= limit_price,
+                Ordertype::Sell => price >= limit_price,
+            };
+            if !crosses {
+                break;
             }
+            // the market maker goes first while it is strictly better than this level
+            self.plan_amm(&stock_type, &ordertype, price, false, &mut quote, &mut remaining, &mut budget);
+            for resting in queue {
+                if remaining == 0 {
+                    break 'levels;
+                }
+                if resting.username == username {
+                    let (cancelled, _) = self_trade_prevention.cancelled_quantities(remaining, resting.total_quantity());
+                    remaining -= cancelled;
+                    continue;
+                }
+                // sells were checked against the holdings up front, so only buys run out of funds
+                let trade_qty = remaining.min(resting.total_quantity()).min(budget.checked_div(price).unwrap_or(u64::MAX));
+                if trade_qty == 0 {
+                    break 'levels;      // out of funds
+                }
+                remaining -= trade_qty;
+                budget -= trade_qty * price;
+                quote.filled += trade_qty;
+                quote.total_cost += trade_qty * price;
+                match quote.fills.last_mut() {
+                    Some(level) if level.price == price => level.quantity += trade_qty,
+                    _ => quote.fills.push(QuoteLevel { price, quantity : trade_qty }),
+                }
+            }
+        }
+        // whatever the book could not fill, up to the order's own limit
+        if remaining > 0 && budget > 0 {
+            let limit = match ordertype {
+                Ordertype::Buy => limit_price.min(PAIR_PAYOUT),
+                Ordertype::Sell => limit_price,
+            };
+            self.plan_amm(&stock_type, &ordertype, limit, true, &mut quote, &mut remaining, &mut budget);
+        }
+        quote.unfilled = quantity - quote.filled;
+        if quote.filled > 0 {
+            quote.average_price = Some(quote.total_cost as f64 / quote.filled as f64);
+        }
+        Ok(quote)
+    }
+    // Adds to `quote` the shares the market maker trades at `price` or better, within what is left of
+    // the order and its budget. Its fills are priced as one trade, so the plan always holds its exact cost.
+    #[allow(clippy::too_many_arguments)]
+    fn plan_amm(&self , stock_type : &StockType , ordertype : &Ordertype , price : u64 , inclusive : bool , quote : &mut Quote , remaining : &mut u64 , budget : &mut u64) {
+        let Some(amm) = &self.amm else {
+            return;
+        };
+        let mut quantity = amm.tradable_within(stock_type, ordertype, quote.amm_filled, *remaining, price, inclusive);
+        if *ordertype == Ordertype::Buy {
+            quantity = amm.affordable(stock_type, quote.amm_filled, quantity, *budget);
+        }
+        if quantity == 0 {
+            return;
+        }
+        let amm_cost = match ordertype {
+            Ordertype::Buy => amm.buy_cost(stock_type, quote.amm_filled + quantity),
+            Ordertype::Sell => amm.sell_proceeds(stock_type, quote.amm_filled + quantity),
+        };
+        *remaining -= quantity;
+        if *ordertype == Ordertype::Buy {
+            *budget -= amm_cost - quote.amm_cost;
+        }
+        quote.filled += quantity;
+        quote.total_cost = quote.total_cost - quote.amm_cost + amm_cost;
+        quote.amm_filled += quantity;
+        quote.amm_cost = amm_cost;
+    }
+    // Trades `quantity` of `stock_type` between `username` and the market maker and records the trade,
+    // on behalf of `order_id` when a market order routes to it. Returns what the user pays for a buy or
+    // receives for a sell, the user side is left to the caller but posted here: the user pays from
+    // `source`, their available or reserved account, and receives into their available account.
+    #[allow(clippy::too_many_arguments)]
+    fn trade_with_amm(&mut self , username : &str , ledger : &mut Ledger , source : Account , order_id : Option<&str> , stock_type : &StockType , ordertype : &Ordertype , quantity : u64) -> Result<(u64, Trade), ExchangeError> {
+        let amm = self.amm.as_mut().ok_or(ExchangeError::NoMarketMaker)?;
+        let collateral = self.collateral;
+        let (amount, from, to) = match ordertype {
+            Ordertype::Buy => (amm.buy(stock_type, quantity, &mut self.collateral)?, AMM_USERNAME.to_string(), username.to_string()),
+            Ordertype::Sell => (amm.sell(stock_type, quantity, &mut self.collateral)?, username.to_string(), AMM_USERNAME.to_string()),
         };
-        match v {
-                Ok(mut v) => {
-                    let v2 = v.clone();
-                    self.trades.append(&mut v);
-                    Ok(v2)
+        let outcomes = amm.inventory.len();
+        let trade = Trade {
+            trade_id : nanoid!(),
+            market_id : self.market_id.clone(),
+            timestamp : Utc::now(),
+            from,
+            to,
+            trade_qty : quantity,
+            trade_price : (amount as f64 / quantity as f64).round() as u64,
+            value : amount,
+            stock_type : stock_type.clone(),
+            kind : TradeKind::Amm,
+            maker_order_id : None,
+            taker_order_id : order_id.map(str::to_string),
+            aggressor : ordertype.clone()
+        };
+        let (maker, user) = (Account::MarketMaker(self.market_id.clone()), Account::Available(username.to_string()));
+        let reference = Reference::trade(&self.market_id, order_id, &trade.trade_id);
+        // sets the market maker minted to sell from, or merged after buying
+        let (reason, sets) = match self.collateral >= collateral {
+            true => (Reason::AmmMint, (self.collateral - collateral) / PAIR_PAYOUT),
+            false => (Reason::AmmMerge, (collateral - self.collateral) / PAIR_PAYOUT),
+        };
+        let (collateral_account, issuance) = (Account::Collateral(self.market_id.clone()), Account::Issuance(self.market_id.clone()));
+        match ordertype {
+            Ordertype::Buy => {
+                ledger.post(maker.clone(), source, Asset::Cash, amount, Reason::AmmTrade, reference.clone());
+                ledger.post(collateral_account, maker.clone(), Asset::Cash, sets * PAIR_PAYOUT, reason, reference.clone());
+                for outcome in 0..outcomes {
+                    ledger.post(maker.clone(), issuance.clone(), Asset::shares(&self.market_id, &StockType(outcome)), sets, reason, reference.clone());
+                }
+                ledger.post(user, maker, Asset::shares(&self.market_id, stock_type), quantity, Reason::AmmTrade, reference);
+            }
+            Ordertype::Sell => {
+                ledger.post(maker.clone(), source, Asset::shares(&self.market_id, stock_type), quantity, Reason::AmmTrade, reference.clone());
+                for outcome in 0..outcomes {
+                    ledger.post(issuance.clone(), maker.clone(), Asset::shares(&self.market_id, &StockType(outcome)), sets, reason, reference.clone());
+                }
+                ledger.post(maker.clone(), collateral_account, Asset::Cash, sets * PAIR_PAYOUT, reason, reference.clone());
+                ledger.post(user, maker, Asset::Cash, amount, Reason::AmmTrade, reference);
+            }
+        }
+        self.trades.push(trade.clone());
+        Ok((amount, trade))
+    }
+    // Buys from or sells to the market maker directly. `limit` is the most a buy pays or the least a
+    // sell receives in total.
+    pub fn trade_amm(&mut self , user : &mut User , ledger : &mut Ledger , stock_type : StockType , ordertype : Ordertype , quantity : u64 , limit : Option<u64>) -> Result<(u64, Trade), ExchangeError> {
+        self.validate_outcome(&stock_type)?;
+        self.validate_quantity(quantity)?;
+        let amm = self.amm.as_ref().ok_or(ExchangeError::NoMarketMaker)?;
+        match ordertype {
+            Ordertype::Buy => {
+                let cost = amm.buy_cost(&stock_type, quantity);
+                if let Some(limit) = limit && cost > limit {
+                    return Err(ExchangeError::LimitExceeded { amount : cost, limit });
+                }
+                if user.balance < cost {
+                    return Err(ExchangeError::InsufficientFunds { required : cost, available : user.balance });
                 }
-                Err(err) => {
-                    Err(err)
+            }
+            Ordertype::Sell => {
+                let proceeds = amm.sell_proceeds(&stock_type, quantity);
+                if let Some(limit) = limit && proceeds < limit {
+                    return Err(ExchangeError::LimitExceeded { amount : proceeds, limit });
+                }
+                let available = user.holdings.get(&self.market_id).map_or(0, |holdings| holdings.stock(&stock_type));
+                if available < quantity {
+                    return Err(ExchangeError::InsufficientStock { required : quantity, available });
+                }
+            }
+        }
+        let source = Account::Available(user.username.clone());
+        let (amount, trade) = self.trade_with_amm(&user.username, ledger, source, None, &stock_type, &ordertype, quantity)?;
+        let holdings = user.holdings.entry(self.market_id.clone()).or_default();
+        match ordertype {
+            Ordertype::Buy => {
+                user.balance -= amount;
+                *holdings.stock_mut(&stock_type) += quantity;
+                user.position_mut(&self.market_id, &stock_type).buy(quantity, amount);
+            }
+            Ordertype::Sell => {
+                *holdings.stock_mut(&stock_type) -= quantity;
+                user.balance += amount;
+                user.position_mut(&self.market_id, &stock_type).sell(quantity, amount);
+            }
+        }
+        Ok((amount, trade))
+    }
+    pub fn ticker(&self , stock_type : &StockType) -> OutcomeTicker {
+        let book = self.book(stock_type);
+        let best_bid = book.best_bid();
+        let best_ask = book.best_ask();
+        let (spread, mid) = match (best_bid, best_ask) {
+            (Some(bid), Some(ask)) => (Some(ask.saturating_sub(bid)), Some((bid + ask) as f64 / 2.0)),
+            _ => (None, None),
+        };
+        OutcomeTicker {
+            best_bid,
+            best_ask,
+            spread,
+            mid,
+            last_price : self.last_trade_price(stock_type),
+            amm_price : self.amm.as_ref().map(|amm| amm.price(stock_type))
+        }
+    }
+    // Price of the last trade in `stock_type`, the exact average of a trade with the market maker.
+    // Mints and burns on the complement trade this outcome at the rest of the pair payout.
+    pub fn last_trade_price(&self , stock_type : &StockType) -> Option<f64> {
+        self.trades.iter().rev().find_map(|trade| {
+            let price = trade.value as f64 / trade.trade_qty as f64;
+            if trade.stock_type == *stock_type {
+                Some(price)
+            } else if matches!(trade.kind, TradeKind::Mint | TradeKind::Burn) {
+                Some(PAIR_PAYOUT as f64 - price)
+            } else {
+                None
+            }
+        })
+    }
+    // Queues a stop or stop-limit order and locks what it will need once triggered: the stock for a
+    // sell, the limit price for a stop-limit buy and the highest possible price for a stop buy. A stop
+    // sell shows the lowest possible price, it locks no cash.
+    pub fn add_stop_order(&mut self , mut stop : StopOrder , user : &mut User , ledger : &mut Ledger) -> Result<(), ExchangeError> {
+        self.validate_outcome(&stop.order.stock_type)?;
+        self.validate_price(stop.trigger_price)?;
+        self.validate_quantity(stop.order.quantity)?;
+        if stop.is_market {
+            stop.order.price = match stop.order.ordertype {
+                Ordertype::Buy => PAIR_PAYOUT - self.tick_size,
+                Ordertype::Sell => self.tick_size,
+            };
+        } else {
+            self.validate_price(stop.order.price)?;
+        }
+        if let Some(last_price) = self.last_trade_price(&stop.order.stock_type) && stop.is_triggered(last_price) {
+            return Err(ExchangeError::StopWouldTrigger);
+        }
+        stop.order.lock(user, ledger)?;
+        self.stop_orders.push(stop);
+        Ok(())
+    }
+    // takes every stop order whose trigger has been crossed, in the order they were submitted
+    pub fn take_triggered_stops(&mut self) -> Vec<StopOrder> {
+        let last_prices : Vec<Option<f64>> = (0..self.outcomes.len()).map(|index| self.last_trade_price(&StockType(index))).collect();
+        let (triggered, pending) = std::mem::take(&mut self.stop_orders).into_iter().partition(|stop : &StopOrder| {
+            last_prices[stop.order.stock_type.0].is_some_and(|last_price| stop.is_triggered(last_price))
+        });
+        self.stop_orders = pending;
+        triggered
+    }
+    // removes a resting or pending stop order and releases the funds or stock locked by it
+    pub fn cancel_order(&mut self , order_id : &str , user : &mut User , ledger : &mut Ledger) -> Option<Order> {
+        let order = self.remove_resting(order_id, &user.username)
+            .or_else(|| {
+                let index = self.stop_orders.iter().position(|stop| stop.order.order_id == order_id && stop.order.username == user.username)?;
+                Some(self.stop_orders.remove(index).order)
+            })?;
+        order.release(user, ledger);
+        Some(order)
+    }
+    pub fn open_orders(&self , username : &str) -> Vec<OpenOrder> {
+        let resting = self.books.iter().flat_map(|book| book.orders_of(username))
+            .map(|order| OpenOrder::new(order, None));
+        let stops = self.stop_orders.iter().filter(|stop| stop.order.username == username)
+            .map(|stop| OpenOrder::new(&stop.order, Some(stop.trigger_price)));
+        resting.chain(stops).collect()
+    }
+    // every outcome of this market the user holds or has traded
+    pub fn positions_of(&self , user : &User) -> Vec<PortfolioPosition> {
+        let Some(positions) = user.positions.get(&self.market_id) else {
+            return Vec::new();
+        };
+        positions.iter().enumerate().filter(|(_, position)| **position != Position::default()).map(|(index, position)| {
+            let stock_type = StockType(index);
+            let mark_price = self.ticker(&stock_type).mid;
+            PortfolioPosition {
+                market_id : self.market_id.clone(),
+                outcome : self.outcomes[index].clone(),
+                quantity : position.quantity,
+                average_price : position.average_price(),
+                cost : position.cost,
+                mark_price,
+                realised_pnl : position.realised_pnl,
+                unrealised_pnl : mark_price.map(|mark_price| position.unrealised_pnl(mark_price)),
+                stock_type
+            }
+        }).collect()
+    }
+    pub fn get_order(&self , order_id : &str , username : &str) -> Option<&Order> {
+        self.books.iter().find_map(|book| book.get_order(order_id, username))
+    }
+    // Reducing only the quantity keeps the order's place in the queue. Changing the price or increasing
+    // the quantity re-queues the order at the back and re-matches it. Only the difference in locked
+    // funds or stock is taken from / released to the user. Returns the new limit price and what became
+    // of the amended quantity.
+    pub fn amend_order(&mut self , order_id : &str , price : Option<u64> , quantity : Option<u64> , user : &mut User , ledger : &mut Ledger) -> Result<(u64, OrderResult), ExchangeError> {
+        let current = self.get_order(order_id, &user.username).ok_or(ExchangeError::OrderNotFound)?.clone();
+        let current_quantity = current.total_quantity();    // including an iceberg's hidden reserve
+        let new_quantity = quantity.unwrap_or(current_quantity);
+        if new_quantity == 0 {
+            return Err(ExchangeError::Validation("Quantity must be greater than zero, cancel the order instead".to_string()));
+        }
+        let new_price = match price {
+            Some(price) if price != current.price => {
+                self.validate_price(price)?;
+                let best_opposite = self.best_counter_price(&current.stock_type, &current.ordertype);
+                current.post_only.price(&current.ordertype, price, best_opposite, self.tick_size)?
+            }
+            _ => current.price,
+        };
+
+        if new_price == current.price && new_quantity <= current_quantity {
+            let order = self.book_mut(&current.stock_type).get_order_mut(order_id, &user.username).ok_or(ExchangeError::OrderNotFound)?;
+            order.reduce_quantity(current_quantity - new_quantity);      // reduced in place, time priority is kept
+            order.original_quantity -= current_quantity - new_quantity;
+            current.release_quantity(current_quantity - new_quantity, user, ledger);
+            return Ok((new_price, OrderResult::new(current.order_id, &current.ordertype, new_quantity, Some(new_price), OrderFill::default())));
+        }
+
+        let (locked, required) = match current.ordertype {
+            Ordertype::Buy => ((order_cost(current.price, current_quantity)?, 0), (order_cost(new_price, new_quantity)?, 0)),
+            Ordertype::Sell => ((0, current_quantity), (0, new_quantity)),
+        };
+        if required.0 > locked.0 || required.1 > locked.1 {
+            user.reserve(ledger, order_id, &self.market_id, &current.stock_type, required.0 - locked.0, required.1 - locked.1)?;
+        } else {
+            user.release(ledger, Reason::OrderReleased, order_id, locked.0 - required.0, locked.1 - required.1);
+        }
+        let mut order = self.book_mut(&current.stock_type).remove_order(order_id, &user.username).ok_or(ExchangeError::OrderNotFound)?;
+        order.price = new_price;
+        order.quantity = new_quantity;
+        order.hidden_quantity = 0;
+        order.original_quantity = order.original_quantity - current_quantity + new_quantity;   // what was filled stays filled
+        order.timestamp = Utc::now();   // loses time priority
+        let fill = self.match_order(&mut order, user, ledger, None);
+        if order.quantity > 0 {
+            order.hide_reserve();
+            self.book_mut(&order.stock_type).book_order(order);
+        }
+        Ok((new_price, OrderResult::new(current.order_id, &current.ordertype, new_quantity, Some(new_price), fill)))
+    }
+    // records the outcome, refunds every resting order and pays out all holders of this market
+    pub fn resolve(&mut self , winning_outcome : WinningOutcome , users : &mut HashMap<String, User> , ledger : &mut Ledger) -> Result<String, ExchangeError> {
+        let outcomes = self.outcomes.len() as u64;
+        let paid_per_share = |outcome : usize| match winning_outcome {
+            WinningOutcome::Outcome(index) if index == outcome => PAIR_PAYOUT,
+            WinningOutcome::Outcome(_) => 0,
+            // rounding dust of uneven splits stays in the collateral
+            WinningOutcome::Neither => PAIR_PAYOUT / outcomes,
+        };
+        // every payout, shares locked by orders included, is checked before anything moves, so the
+        // unchecked sums below stay within their total
+        let overflow = || ExchangeError::Validation("Payouts of the market overflow".to_string());
+        let mut held : Vec<Vec<u64>> = users.values().filter_map(|user| user.holdings.get(&self.market_id))
+            .map(|holdings| (0..self.outcomes.len()).map(|outcome| holdings.stock(&StockType(outcome)).checked_add(holdings.reserved(&StockType(outcome)))).collect::<Option<_>>())
+            .collect::<Option<_>>().ok_or_else(overflow)?;
+        held.extend(self.amm.as_ref().map(|amm| amm.inventory.clone()));
+        held.iter().flat_map(|stocks| stocks.iter().enumerate())
+            .try_fold(0u64, |total, (outcome, held)| held.checked_mul(paid_per_share(outcome)).and_then(|payout| total.checked_add(payout)))
+            .ok_or_else(overflow)?;
+
+        let mut resting : Vec<Order> = self.books.iter_mut().flat_map(|book| book.drain_orders()).collect();
+        resting.extend(self.stop_orders.drain(..).map(|stop| stop.order));
+        for order in resting {
+            if let Some(user) = users.get_mut(&order.username) {
+                order.release(user, ledger);    // released stock is paid out below
+            }
+        }
+        self.expiries.clear();
+
+        let payout_of = |stocks : &[u64]| stocks.iter().enumerate().map(|(outcome, held)| held * paid_per_share(outcome)).sum::<u64>();
+        let (collateral, issuance) = (Account::Collateral(self.market_id.clone()), Account::Issuance(self.market_id.clone()));
+        let reference = Reference::market(&self.market_id);
+        // every share held is retired and the winning ones are paid out of the collateral
+        let settle = |ledger : &mut Ledger , holder : Account , stocks : &[u64]| {
+            for (outcome, held) in stocks.iter().enumerate() {
+                ledger.post(issuance.clone(), holder.clone(), Asset::shares(&self.market_id, &StockType(outcome)), *held, Reason::Settlement, reference.clone());
+            }
+            let payout = payout_of(stocks);
+            ledger.post(holder, collateral.clone(), Asset::Cash, payout, Reason::Settlement, reference.clone());
+            payout
+        };
+        let mut total_payout = 0;
+        for user in users.values_mut() {
+            if let Some(holdings) = user.holdings.remove(&self.market_id) {
+                // settling closes every position at its payout
+                for (outcome, held) in holdings.stocks.iter().enumerate() {
+                    user.position_mut(&self.market_id, &StockType(outcome)).sell(*held, held * paid_per_share(outcome));
                 }
+                let payout = settle(ledger, Account::Available(user.username.clone()), &holdings.stocks);
+                user.balance += payout;
+                total_payout += payout;
             }
+        }
+        // the market maker's inventory is paid out too, and what is left of it goes back to the creator
+        if let Some(amm) = &mut self.amm {
+            let maker = Account::MarketMaker(self.market_id.clone());
+            let payout = settle(ledger, maker.clone(), &amm.inventory);
+            total_payout += payout;
+            if let Some(creator) = users.get_mut(&self.created_by) {
+                creator.balance += amm.cash + payout;
+                ledger.post(Account::Available(creator.username.clone()), maker, Asset::Cash, amm.cash + payout, Reason::MarketMakerReturn, reference.clone());
+            }
+            amm.cash = 0;
+            amm.inventory.iter_mut().for_each(|held| *held = 0);
+        }
+        self.collateral -= total_payout;    // every full set is paid out exactly once
+        self.winning_outcome = Some(winning_outcome);
+        self.is_settled = true;
+        Ok(format!("Market settled as {:?}, paid out {}", winning_outcome, total_payout))
     }
-}
\ No newline at end of file
+}
@@ -5,4 +5,16 @@ pub use market::*;
 pub mod order;
 pub use order::*;
 pub mod request;
-pub use request::*;
\ No newline at end of file
+pub use request::*;
+pub mod amm;
+pub use amm::*;
+pub mod error;
+pub use error::*;
+pub mod ledger;
+pub use ledger::*;
+pub mod solvency;
+pub use solvency::*;
+pub mod funding;
+pub use funding::*;
+pub mod position;
+pub use position::*;
\ No newline at end of file
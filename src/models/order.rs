@@ -3,206 +3,495 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::{ BTreeMap , VecDeque};
 
-use crate::User;
+use crate::{ExchangeError, Ledger, Reason, User, PAIR_PAYOUT};
 
 #[derive(Debug , Clone  , Serialize )]
 pub struct Order {
+   pub order_id : String,
    pub price : u64, 
-   pub quantity : u64,
+   pub quantity : u64,     // displayed quantity, an iceberg keeps the rest hidden
    pub stock_type: StockType,
    pub username : String,
    pub timestamp: DateTime<Utc>,
    pub ordertype: Ordertype,
-   pub market_id : String
+   pub market_id : String,
+   pub time_in_force : TimeInForce,
+   pub post_only : PostOnly,
+   pub self_trade_prevention : SelfTradePrevention,
+   #[serde(skip)]
+   pub display_quantity : Option<u64>,     // slice an iceberg order shows at a time
+   #[serde(skip)]
+   pub hidden_quantity : u64,
+   pub original_quantity : u64     // total quantity as submitted or last amended
 }
-#[derive(Debug , Clone )]
+impl Order {
+    pub fn total_quantity(&self) -> u64 {
+        self.quantity + self.hidden_quantity
+    }
+    // moves everything above the display quantity into the hidden reserve before the order rests
+    pub fn hide_reserve(&mut self) {
+        if let Some(display_quantity) = self.display_quantity && self.quantity > display_quantity {
+            self.hidden_quantity += self.quantity - display_quantity;
+            self.quantity = display_quantity;
+        }
+    }
+    // shows the next slice once the displayed one is used up, the order then queues as a new one
+    pub fn replenish(&mut self) -> bool {
+        if self.quantity > 0 || self.hidden_quantity == 0 {
+            return false;
+        }
+        let slice = self.display_quantity.unwrap_or(self.hidden_quantity).min(self.hidden_quantity);
+        self.quantity = slice;
+        self.hidden_quantity -= slice;
+        self.timestamp = Utc::now();
+        true
+    }
+    // takes a cancelled quantity out of the hidden reserve first, then out of the displayed slice
+    pub fn reduce_quantity(&mut self , quantity : u64) {
+        let from_hidden = quantity.min(self.hidden_quantity);
+        self.hidden_quantity -= from_hidden;
+        self.quantity -= quantity - from_hidden;
+    }
+    // reserves the funds (buy) or stock (sell) needed for the whole order under its id
+    pub fn lock(&self , user : &mut User , ledger : &mut Ledger) -> Result<(), ExchangeError> {
+        let (balance, stock) = self.locked()?;
+        user.reserve(ledger, &self.order_id, &self.market_id, &self.stock_type, balance, stock)
+    }
+    // balance (buy) or stock (sell) the order needs locked, failing when its cost overflows
+    pub fn locked(&self) -> Result<(u64, u64), ExchangeError> {
+        match self.ordertype {
+            Ordertype::Buy => Ok((order_cost(self.price, self.total_quantity())?, 0)),
+            Ordertype::Sell => Ok((0, self.total_quantity())),
+        }
+    }
+    // gives back the funds (buy) or stock (sell) locked for the unfilled quantity
+    pub fn release(&self , user : &mut User , ledger : &mut Ledger) {
+        self.release_quantity(self.total_quantity(), user, ledger);
+    }
+    pub fn release_quantity(&self , quantity : u64 , user : &mut User , ledger : &mut Ledger) {
+        match self.ordertype {
+            Ordertype::Buy => user.release(ledger, Reason::OrderReleased, &self.order_id, self.price * quantity, 0),
+            Ordertype::Sell => user.release(ledger, Reason::OrderReleased, &self.order_id, 0, quantity),
+        }
+    }
+}
+// what `quantity` shares cost at `price`
+pub fn order_cost(price : u64 , quantity : u64) -> Result<u64, ExchangeError> {
+    price.checked_mul(quantity).ok_or_else(|| ExchangeError::Validation(format!("Cost of {} shares at {} overflows", quantity, price)))
+}
+#[derive(Debug , Clone , PartialEq , Serialize)]
 pub struct Trade {
+    pub trade_id : String,
+    pub market_id : String,
+    pub timestamp : DateTime<Utc>,
     pub from : String,  // always the user who's stocks are sold (seller)
     pub to : String ,   // always the user who buys the stocks (buyer)
     pub trade_qty : u64 ,
-    pub trade_price : u64 ,
-    pub stock_type : StockType
+    pub trade_price : u64 ,     // per share, the rounded average of `value` for a market maker trade
+    pub value : u64 ,           // paid for all shares, exact for every kind of trade
+    pub stock_type : StockType,
+    pub kind : TradeKind,
+    pub maker_order_id : Option<String>,    // resting order, None when the market maker is the counterparty
+    pub taker_order_id : Option<String>,    // incoming order, None for a direct trade with the market maker
+    pub aggressor : Ordertype               // side of the taker
+}
+// How a trade is backed. For Mint and Burn the counterparty trades the complementary stock
+// at PAIR_PAYOUT - trade_price, and `from` / `to` are the two users on the same side.
+#[derive(Debug , Clone , Copy , PartialEq , Serialize)]
+pub enum TradeKind {
+    Transfer,   // stock moves from the seller to the buyer
+    Mint,       // `to` buys `stock_type` and `from` buys the complement, a new pair is minted
+    Burn,       // `from` sells `stock_type` and `to` sells the complement, the pair is burned
+    Amm         // one side is the market maker, the user side is settled by the market itself
 }
+// Index of an outcome within its market. Binary markets keep the StockA / StockB names, and the
+// legacy names are still accepted on the wire.
 #[derive(Debug , Clone , PartialEq, Hash, Eq , Deserialize , Serialize)]
-pub enum StockType {
+#[serde(from = "StockTypeRepr")]
+pub struct StockType(pub usize);
+#[allow(non_upper_case_globals)]
+impl StockType {
+    pub const StockA : StockType = StockType(0);
+    pub const StockB : StockType = StockType(1);
+    // the other outcome of a binary market
+    pub fn complement(&self) -> StockType {
+        StockType(1 - self.0)
+    }
+}
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StockTypeRepr {
+    Index(usize),
+    Named(LegacyStockType)
+}
+#[derive(Deserialize)]
+enum LegacyStockType {
     StockA,
-    StockB 
+    StockB
+}
+// the same names and indices in a query string, where every value is text
+impl std::str::FromStr for StockType {
+    type Err = ExchangeError;
+    fn from_str(value : &str) -> Result<Self, Self::Err> {
+        match value {
+            "StockA" => Ok(StockType::StockA),
+            "StockB" => Ok(StockType::StockB),
+            index => index.parse().map(StockType).map_err(|_| ExchangeError::Validation(format!("Unknown stock type {}", value))),
+        }
+    }
+}
+impl From<StockTypeRepr> for StockType {
+    fn from(repr : StockTypeRepr) -> Self {
+        match repr {
+            StockTypeRepr::Index(index) => StockType(index),
+            StockTypeRepr::Named(LegacyStockType::StockA) => StockType::StockA,
+            StockTypeRepr::Named(LegacyStockType::StockB) => StockType::StockB,
+        }
+    }
 }
-#[derive(Debug , Clone , Deserialize , Serialize)]
+#[derive(Debug , Clone , PartialEq , Deserialize , Serialize)]
 pub enum Ordertype{
     Buy,
     Sell
 }
+#[derive(Debug , Clone , Copy , Default , PartialEq , Deserialize , Serialize)]
+pub enum TimeInForce {
+    #[default]
+    GoodTillCancel,                 // rests until filled or cancelled
+    ImmediateOrCancel,              // fills what matches now, the rest is cancelled
+    FillOrKill,                     // fills completely right away or not at all
+    GoodTillDate(DateTime<Utc>)     // rests until filled, cancelled or expired
+}
+#[derive(Debug , Clone , Copy , Default , PartialEq , Deserialize , Serialize)]
+pub enum PostOnly {
+    #[default]
+    Disabled,
+    Reject,     // reject the order if it would trade on arrival
+    Reprice     // move the order one tick behind the best opposite price instead of trading
+}
+impl PostOnly {
+    // price at which a post-only order can rest without trading against `best_opposite`, one tick away from it
+    pub fn price(&self , ordertype : &Ordertype , price : u64 , best_opposite : Option<u64> , tick_size : u64) -> Result<u64, ExchangeError> {
+        let improved = match (ordertype, best_opposite) {
+            (Ordertype::Buy, Some(best_ask)) if price >= best_ask => best_ask.checked_sub(tick_size).filter(|&p| p > 0),
+            (Ordertype::Sell, Some(best_bid)) if price <= best_bid => Some(best_bid + tick_size).filter(|&p| p < PAIR_PAYOUT),
+            _ => return Ok(price),
+        };
+        match (self, improved) {
+            (PostOnly::Disabled, _) => Ok(price),
+            (PostOnly::Reprice, Some(improved)) => Ok(improved),
+            _ => Err(ExchangeError::PostOnlyWouldTrade),
+        }
+    }
+}
+// what happens when an incoming order meets a resting order of the same user
+#[derive(Debug , Clone , Copy , Default , PartialEq , Deserialize , Serialize)]
+pub enum SelfTradePrevention {
+    #[default]
+    CancelNewest,       // cancel the rest of the incoming order
+    CancelOldest,       // cancel the resting order and keep matching
+    CancelBoth,         // cancel both orders
+    DecrementAndCancel  // reduce both by the smaller quantity, cancelling the smaller one
+}
+impl SelfTradePrevention {
+    // quantities to cancel from the incoming and the resting order
+    pub fn cancelled_quantities(&self , incoming : u64 , resting : u64) -> (u64, u64) {
+        match self {
+            SelfTradePrevention::CancelNewest => (incoming, 0),
+            SelfTradePrevention::CancelOldest => (0, resting),
+            SelfTradePrevention::CancelBoth => (incoming, resting),
+            SelfTradePrevention::DecrementAndCancel => (incoming.min(resting), incoming.min(resting)),
+        }
+    }
+}
+// Conditional order waiting in its market until the last trade price crosses `trigger_price`: buys
+// trigger at or above it, sells at or below it. Its funds or stock are locked from submission.
+#[derive(Debug , Clone , Serialize)]
+pub struct StopOrder {
+    pub order : Order,          // placed once triggered, as a market order for plain stops
+    pub trigger_price : u64,
+    pub is_market : bool        // stop (true) or stop-limit (false)
+}
+impl StopOrder {
+    pub fn is_triggered(&self , last_price : f64) -> bool {
+        match self.order.ordertype {
+            Ordertype::Buy => last_price >= self.trigger_price as f64,
+            Ordertype::Sell => last_price <= self.trigger_price as f64,
+        }
+    }
+}
+// worst acceptable prices and spend for a market order sweeping the book
+#[derive(Debug , Clone , Copy , Default , PartialEq , Deserialize , Serialize)]
+pub struct SlippageLimits {
+    pub max_price : Option<u64>,        // buys stop before paying more than this per share
+    pub min_price : Option<u64>,        // sells stop before receiving less than this per share
+    pub max_total_cost : Option<u64>    // buys stop before spending more than this in total
+}
+// outcome of matching an order
+#[derive(Debug , Clone , Default)]
+pub struct OrderFill {
+    pub trades : Vec<Trade>,
+    pub cancelled : u64     // unfilled quantity that was cancelled instead of booked
+}
+impl OrderFill {
+    pub fn filled_quantity(&self) -> u64 {
+        self.trades.iter().map(|trade| trade.trade_qty).sum()
+    }
+}
+#[derive(Debug , Clone , Copy , PartialEq , Serialize)]
+pub enum OrderStatus {
+    Resting,            // nothing filled, the order waits in the book
+    PartiallyFilled,    // some of it filled, the rest waits in the book
+    Filled,
+    PartiallyFilledCancelled,   // some of it filled, the rest was cancelled instead of resting
+    Cancelled           // nothing filled, the whole order was cancelled
+}
+// what became of an order when it was placed, as reported back to the user who placed it
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct OrderResult {
+    pub order_id : String,
+    pub status : OrderStatus,
+    pub filled : u64,
+    pub remaining : u64,                // resting in the book, including an iceberg's hidden reserve
+    pub cancelled : u64,
+    pub average_price : Option<f64>,    // None when nothing filled
+    pub trades : Vec<Trade>,
+    pub refunded : u64                  // given back to a buy that filled below its limit price
+}
+impl OrderResult {
+    // `limit_price` is set for limit orders, whose buys locked that price for every share
+    pub fn new(order_id : String , ordertype : &Ordertype , quantity : u64 , limit_price : Option<u64> , fill : OrderFill) -> Self {
+        let filled = fill.filled_quantity();
+        let remaining = quantity - filled - fill.cancelled;
+        let status = match (remaining, filled, fill.cancelled) {
+            (0, _, 0) => OrderStatus::Filled,
+            (0, 0, _) => OrderStatus::Cancelled,
+            (0, _, _) => OrderStatus::PartiallyFilledCancelled,
+            (_, 0, _) => OrderStatus::Resting,
+            _ => OrderStatus::PartiallyFilled,
+        };
+        let value : u64 = fill.trades.iter().map(|trade| trade.value).sum();
+        let refunded = match (ordertype, limit_price) {
+            (Ordertype::Buy, Some(limit_price)) => limit_price * filled - value,
+            _ => 0,
+        };
+        OrderResult {
+            order_id,
+            status,
+            filled,
+            remaining,
+            cancelled : fill.cancelled,
+            average_price : (filled > 0).then(|| value as f64 / filled as f64),
+            trades : fill.trades,
+            refunded
+        }
+    }
+}
+// a direct trade with the market maker and what it cost (buy) or paid (sell) in total
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct AmmTradeResult {
+    pub amount : u64,
+    pub trade : Trade
+}
+// expected outcome of a market order, computed without touching the book
+#[derive(Debug , Clone , Default , PartialEq , Serialize)]
+pub struct Quote {
+    pub fills : Vec<QuoteLevel>,        // best level first
+    pub filled : u64,
+    pub average_price : Option<f64>,    // None when nothing would fill
+    pub total_cost : u64,               // paid for a buy, received for a sell
+    pub unfilled : u64,
+    pub amm_filled : u64,               // part of `filled` traded with the market maker
+    pub amm_cost : u64                  // part of `total_cost` paid to / received from the market maker
+}
+#[derive(Debug , Clone , Copy , PartialEq , Serialize)]
+pub struct QuoteLevel {
+    pub price : u64,
+    pub quantity : u64
+}
+// resting order as anyone can see it, without who placed it or its hidden reserve
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct PublicOrder {
+    pub order_id : String,
+    pub price : u64,
+    pub quantity : u64,
+    pub timestamp : DateTime<Utc>
+}
+#[derive(Debug , Clone , Default , PartialEq , Serialize)]
+pub struct PublicOrderBook {
+    pub buy : BTreeMap<u64, Vec<PublicOrder>>,
+    pub sell : BTreeMap<u64, Vec<PublicOrder>>
+}
+// one of the user's own orders, resting in a book or waiting for its stop trigger
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct OpenOrder {
+    pub order_id : String,
+    pub market_id : String,
+    pub stock_type : StockType,
+    pub ordertype : Ordertype,
+    pub price : u64,
+    pub remaining_quantity : u64,       // including an iceberg's hidden reserve
+    pub original_quantity : u64,
+    pub displayed_quantity : u64,
+    pub locked_balance : u64,
+    pub locked_stock : u64,
+    pub trigger_price : Option<u64>,    // set for pending stop orders
+    pub time_in_force : TimeInForce,
+    pub timestamp : DateTime<Utc>
+}
+impl OpenOrder {
+    pub fn new(order : &Order , trigger_price : Option<u64>) -> Self {
+        // an order is only open once its lock succeeded, so its cost fits
+        let (locked_balance, locked_stock) = order.locked().unwrap_or_default();
+        OpenOrder {
+            order_id : order.order_id.clone(),
+            market_id : order.market_id.clone(),
+            stock_type : order.stock_type.clone(),
+            ordertype : order.ordertype.clone(),
+            price : order.price,
+            remaining_quantity : order.total_quantity(),
+            original_quantity : order.original_quantity,
+            displayed_quantity : if trigger_price.is_some() { 0 } else { order.quantity },
+            locked_balance,
+            locked_stock,
+            trigger_price,
+            time_in_force : order.time_in_force,
+            timestamp : order.timestamp
+        }
+    }
+}
+// total displayed quantity resting at one price
+#[derive(Debug , Clone , Copy , PartialEq , Serialize)]
+pub struct DepthLevel {
+    pub price : u64,
+    pub quantity : u64
+}
+// aggregated view of one book, best price first on both sides
+#[derive(Debug , Clone , Default , PartialEq , Serialize)]
+pub struct BookDepth {
+    pub bids : Vec<DepthLevel>,
+    pub asks : Vec<DepthLevel>
+}
 #[derive(Debug, Serialize , Clone)]
 pub struct OrderBook {
     pub buy: BTreeMap<u64, VecDeque<Order>>,
     pub sell: BTreeMap<u64, VecDeque<Order>>,
 }
+impl Default for OrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl OrderBook {
     pub fn new () -> Self{
         Self { buy: BTreeMap::new(), sell: BTreeMap::new() }
     }
-    pub fn add_limit_order(
-        &mut self,
-        mut order : Order,
-        user : &mut User
-    )-> Result<Vec<Trade> , String> {
-        let mut trades = vec![];
-        match order.ordertype {
-            Ordertype::Buy => {
-                let required_balance = order.price * order.quantity;
-                if user.balance < order.price * order.quantity {
-                    return Err(format!("Insufficient funds. Required: {}, Available: {}", required_balance, user.balance));
-                }
-                user.balance -= order.price * order.quantity; // Funds locked immediately
-
-                while let Some((&lowest_sell_price , queue)) = self.sell.iter_mut().next(){
-                    if order.price >= lowest_sell_price && order.quantity > 0{
-                        if let Some (mut sell_order) = queue.pop_front(){
-                            let trade_qty = order.quantity.min(sell_order.quantity);  // minimum quantity out of buy order and sell order popped from queue
-                            order.quantity -= trade_qty;            //  minimum qty can only be matched
-                            sell_order.quantity -= trade_qty;
-
-                            trades.push(Trade{
-                                from : sell_order.username.clone(),
-                                to : order.username.clone(),
-                                trade_qty,
-                                trade_price : lowest_sell_price,
-                                stock_type : sell_order.stock_type.clone()
-                            });
-                            if sell_order.quantity > 0 {          // if there is still qty left for sellorder, push it back to front of queue
-                                queue.push_front(sell_order);
-                            }
-                        }else{
-                            self.sell.remove(&lowest_sell_price);
-                        }
-                    }else{
-                        break
-                    }
-                }
-                if order.quantity > 0 {
-                    self.buy.entry(order.price).or_insert_with(VecDeque::new).push_back(order);  // after the loop , if buy order has qty left , push it to buy BTREE
-                }
-            }
-            Ordertype::Sell => {
-                let holdings = user.holdings.entry(order.market_id.clone()).or_default();
-                let available_stock = match order.stock_type {
-                        StockType::StockA => &mut holdings.stock_a,
-                        StockType::StockB => &mut holdings.stock_b,
-                };
-                if *available_stock < order.quantity {
-                        return Err(format!("Insufficient stock. Required: {}, Available: {}", order.quantity, available_stock));
-                }
-                *available_stock -= order.quantity;  // lock the users stock 
-                while let Some((&highest_buy_price, queue)) = self.buy.iter_mut().next_back() {
-                    if order.price <= highest_buy_price && order.quantity > 0 {
-                        if let Some(mut buy_order) = queue.pop_front() {
-                            let trade_qty = order.quantity.min(buy_order.quantity);
-                            order.quantity -= trade_qty;
-                            buy_order.quantity -= trade_qty;
-                            trades.push(Trade { 
-                                from: order.username.clone(), 
-                                to: buy_order.username.clone(),
-                                trade_qty, 
-                                trade_price: highest_buy_price,
-                                stock_type : buy_order.stock_type.clone()
-                            });
-
-                            if buy_order.quantity > 0 {
-                                queue.push_front(buy_order);
-                            }
-                        } else {
-                            self.buy.remove(&highest_buy_price);
-                        }
-                    } else {
-                        break; // no matching buy
-                    }
-                }
-
-                if order.quantity > 0 {
-                    self.sell.entry(order.price).or_insert_with(VecDeque::new).push_back(order);
+    // removes a resting order of the user from whichever side and price level it rests at
+    pub fn remove_order(&mut self , order_id : &str , username : &str) -> Option<Order> {
+        for side in [&mut self.buy, &mut self.sell] {
+            let found = side.iter().find_map(|(&price, queue)| {
+                queue.iter().position(|o| o.order_id == order_id && o.username == username).map(|index| (price, index))
+            });
+            if let Some((price, index)) = found {
+                let queue = side.get_mut(&price)?;
+                let order = queue.remove(index);
+                if queue.is_empty() {
+                    side.remove(&price);   // drop empty price level
                 }
+                return order;
             }
         }
-        Ok(trades)
-    }
-    
-    pub fn execute_market_order(&mut self , username : String , ordertype : Ordertype , mut quantity : u64 , user : &mut User , market_id : String , stock_type : StockType ) -> Result<Vec<Trade>, String> {
-        let mut trades = vec![];
-        match ordertype {
-            Ordertype::Buy => {
-                while quantity > 0 {
-                    if let Some((&lowest_sell_price, queue)) = self.sell.iter_mut().next() {
-                        if let Some(mut sell_order) = queue.pop_front() {
-                            let trade_qty = quantity.min(sell_order.quantity);
-                            if user.balance < trade_qty * lowest_sell_price{
-                                break;
-                            }
-                            quantity -= trade_qty;
-                            sell_order.quantity -= trade_qty;
-                            user.balance -= trade_qty * lowest_sell_price;
-                            trades.push(
-                                Trade{
-                                    from : sell_order.username.clone(),
-                                    to : username.clone(),
-                                    trade_qty,
-                                    trade_price : lowest_sell_price,
-                                    stock_type : sell_order.stock_type.clone()
-                                }
-                            );
-
-                            if sell_order.quantity > 0 {
-                                queue.push_front(sell_order);
-                            }
-                        } else {
-                            self.sell.remove(&lowest_sell_price);
-                        }
-                    } else {
-                         break; // no sells left
-                    }
-                }
-            }
-
-            Ordertype::Sell => {
-                let holdings = user.holdings.entry(market_id.clone()).or_default();
-                let available_stock = match stock_type {
-                        StockType::StockA => &mut holdings.stock_a,
-                        StockType::StockB => &mut holdings.stock_b,
-                };
-                // return error if user has less stock than he is selling
-                 if *available_stock < quantity {
-                    return Err(format!("Insufficient stock. Required: {}, Available: {}", quantity, available_stock));
-                 }
-
-                while quantity > 0 {
-                    if let Some((&highest_buy_price, queue)) = self.buy.iter_mut().next_back() {
-                        if let Some(mut buy_order) = queue.pop_front() {
-                            let trade_qty = quantity.min(buy_order.quantity);
-                           
-                            quantity -= trade_qty;
-                            buy_order.quantity -= trade_qty;
-                            *available_stock -= trade_qty;  // lock the users stock 
-
-                            trades.push(Trade { 
-                                from: username.clone(), 
-                                to: buy_order.username.clone(), 
-                                trade_qty, 
-                                trade_price: highest_buy_price,
-                                stock_type : buy_order.stock_type.clone()
-                            });
-
-                            if buy_order.quantity > 0 {
-                                queue.push_front(buy_order);
-                            }
-                        } else {
-                            self.buy.remove(&highest_buy_price);
-                        }
-                    } else {
-                        break; // no buys left
-                    }
-                }
-            }
+        None
+    }
+    // removes every resting order from both sides of the book
+    pub fn drain_orders(&mut self) -> Vec<Order> {
+        let buys = std::mem::take(&mut self.buy);
+        let sells = std::mem::take(&mut self.sell);
+        buys.into_values().chain(sells.into_values()).flatten().collect()
+    }
+    pub fn get_order(&self , order_id : &str , username : &str) -> Option<&Order> {
+        self.buy.values().chain(self.sell.values())
+            .flat_map(|queue| queue.iter())
+            .find(|o| o.order_id == order_id && o.username == username)
+    }
+    // finds a resting order of the user so it can be modified in place, keeping its queue position
+    pub fn get_order_mut(&mut self , order_id : &str , username : &str) -> Option<&mut Order> {
+        self.buy.values_mut().chain(self.sell.values_mut())
+            .flat_map(|queue| queue.iter_mut())
+            .find(|o| o.order_id == order_id && o.username == username)
+    }
+    pub fn best_bid(&self) -> Option<u64> {
+        self.buy.iter().rev().find(|(_, queue)| !queue.is_empty()).map(|(&price, _)| price)
+    }
+    pub fn best_ask(&self) -> Option<u64> {
+        self.sell.iter().find(|(_, queue)| !queue.is_empty()).map(|(&price, _)| price)
+    }
+    // takes the first order at a price level, dropping the level once it is empty
+    pub fn pop_front(&mut self , side : &Ordertype , price : u64) -> Option<Order> {
+        let levels = match side {
+            Ordertype::Buy => &mut self.buy,
+            Ordertype::Sell => &mut self.sell,
+        };
+        let queue = levels.get_mut(&price)?;
+        let order = queue.pop_front();
+        if queue.is_empty() {
+            levels.remove(&price);
+        }
+        order
+    }
+    // puts a partially filled order back at the front of its price level
+    pub fn push_front(&mut self , order : Order) {
+        match order.ordertype {
+            Ordertype::Buy => self.buy.entry(order.price).or_default().push_front(order),
+            Ordertype::Sell => self.sell.entry(order.price).or_default().push_front(order),
+        }
+    }
+    // Puts a resting order back after it was matched: at the front while its displayed slice lasts, at
+    // the back of the level once an iceberg shows its next slice, not at all once nothing is left.
+    pub fn requeue(&mut self , mut order : Order) {
+        if order.quantity > 0 {
+            self.push_front(order);
+        } else if order.replenish() {
+            self.book_order(order);
+        }
+    }
+    pub fn public_view(&self) -> PublicOrderBook {
+        let level = |(&price, queue) : (&u64, &VecDeque<Order>)| {
+            let orders = queue.iter().map(|order| PublicOrder {
+                order_id : order.order_id.clone(),
+                price : order.price,
+                quantity : order.quantity,
+                timestamp : order.timestamp
+            });
+            (price, orders.collect())
+        };
+        PublicOrderBook {
+            buy : self.buy.iter().map(level).collect(),
+            sell : self.sell.iter().map(level).collect()
+        }
+    }
+    // resting orders of one user, oldest first
+    pub fn orders_of<'a>(&'a self , username : &'a str) -> impl Iterator<Item = &'a Order> + 'a {
+        self.buy.values().chain(self.sell.values())
+            .flat_map(|queue| queue.iter())
+            .filter(move |order| order.username == username)
+    }
+    // the best `levels` prices of each side with their total displayed quantity
+    pub fn depth(&self , levels : usize) -> BookDepth {
+        let level = |(&price, queue) : (&u64, &VecDeque<Order>)| DepthLevel {
+            price,
+            quantity : queue.iter().map(|order| order.quantity).sum()
+        };
+        BookDepth {
+            bids : self.buy.iter().rev().take(levels).map(level).collect(),
+            asks : self.sell.iter().take(levels).map(level).collect()
+        }
+    }
+    // queues an order at the back of its price level
+    pub fn book_order(&mut self , order : Order) {
+        match order.ordertype {
+            Ordertype::Buy => self.buy.entry(order.price).or_default().push_back(order),
+            Ordertype::Sell => self.sell.entry(order.price).or_default().push_back(order),
         }
-       Ok(trades)
     }
-}
\ No newline at end of file
+}
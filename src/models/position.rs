@@ -0,0 +1,41 @@
+use serde::Serialize;
+
+// A user's position in one outcome of a market: the shares held, available or reserved, what they
+// cost and the profit or loss taken on shares already sold, merged or settled.
+#[derive(Debug , Default , Clone , PartialEq , Serialize)]
+pub struct Position {
+    pub quantity : u64,
+    pub cost : u64,             // cost basis of the shares held
+    pub realised_pnl : i64
+}
+impl Position {
+    pub fn average_price(&self) -> Option<f64> {
+        (self.quantity > 0).then(|| self.cost as f64 / self.quantity as f64)
+    }
+    // adds `quantity` shares bought for `cost` in total
+    pub fn buy(&mut self , quantity : u64 , cost : u64) {
+        self.quantity += quantity;
+        self.cost += cost;
+    }
+    // Takes `quantity` shares out at their average cost and realises the difference to `proceeds`.
+    // Selling the whole position takes out all of its cost, so rounding never leaves any behind.
+    pub fn sell(&mut self , quantity : u64 , proceeds : u64) {
+        let cost = match quantity >= self.quantity {
+            true => self.cost,
+            false => (self.cost as u128 * quantity as u128 / self.quantity as u128) as u64,
+        };
+        self.quantity -= quantity;
+        self.cost -= cost;
+        self.realised_pnl += proceeds as i64 - cost as i64;
+    }
+    // profit or loss of the shares held if they were sold at `mark_price`
+    pub fn unrealised_pnl(&self , mark_price : f64) -> f64 {
+        mark_price * self.quantity as f64 - self.cost as f64
+    }
+}
+// Shares `amount` between `parts` outcomes as evenly as whole units allow, e.g. the price of full
+// sets between their outcomes. The first outcomes take the remainder.
+pub fn split_evenly(amount : u64 , parts : usize) -> Vec<u64> {
+    let parts = parts as u64;
+    (0..parts).map(|index| amount / parts + u64::from(index < amount % parts)).collect()
+}
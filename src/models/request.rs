@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use crate::{UserHoldings, order::*};
+use crate::{Deposit, ExchangeError, JournalEntry, Reservation, SolvencyReport, Withdrawal, WithdrawalDecision, UserHoldings, WinningOutcome, order::*};
 use serde::Serialize;
 use tokio::sync::oneshot;
 
@@ -9,12 +9,12 @@ pub enum Request {
     Signup {
         username: String,
         password : String,
-        resp: oneshot::Sender<Result<String, String>>
+        resp: oneshot::Sender<Result<String, ExchangeError>>
     },
     Signin {
         username: String,
         password : String,
-        resp: oneshot::Sender<Result<String, String>>
+        resp: oneshot::Sender<Result<String, ExchangeError>>
     },
     CreateLimitOrder{
         username : String,
@@ -23,7 +23,11 @@ pub enum Request {
         quantity : u64,
         ordertype : Ordertype,
         market_id : String, 
-        resp: oneshot::Sender<Result<String, String>>
+        time_in_force : TimeInForce,
+        post_only : PostOnly,
+        self_trade_prevention : SelfTradePrevention,
+        display_quantity : Option<u64>, // iceberg order showing this much at a time
+        resp: oneshot::Sender<Result<OrderResult, ExchangeError>>
     },
     CreateMarketOrder {
     username: String,
@@ -31,43 +35,193 @@ pub enum Request {
     quantity: u64,
     ordertype: Ordertype,
     market_id : String,
-    resp: oneshot::Sender<Result<String, String>>,
+    self_trade_prevention : SelfTradePrevention,
+    limits : SlippageLimits,
+    all_or_nothing : bool,  // fill the whole quantity or nothing
+    resp: oneshot::Sender<Result<OrderResult, ExchangeError>>,
+    },
+    CreateStopOrder {
+        username: String,
+        stock_type: StockType,
+        quantity: u64,
+        ordertype: Ordertype,
+        market_id : String,
+        trigger_price : u64,
+        limit_price : Option<u64>,  // stop-limit when set, otherwise a stop that becomes a market order
+        self_trade_prevention : SelfTradePrevention,
+        resp: oneshot::Sender<Result<LimitOrderResponse, ExchangeError>>,
+    },
+    QuoteMarketOrder {
+        username: String,
+        stock_type: StockType,
+        quantity: u64,
+        ordertype: Ordertype,
+        market_id : String,
+        self_trade_prevention : SelfTradePrevention,
+        limits : SlippageLimits,
+        resp: oneshot::Sender<Result<Quote, ExchangeError>>,
+    },
+    TradeAmm {
+        username: String,
+        stock_type: StockType,
+        quantity: u64,
+        ordertype: Ordertype,
+        market_id : String,
+        limit : Option<u64>,    // most a buy pays or least a sell receives in total
+        resp: oneshot::Sender<Result<AmmTradeResult, ExchangeError>>,
     },
     CreateMarket{
         username : String,
         market_name : String,
-        resp: oneshot::Sender<Result<String, String>>
+        tick_size : Option<u64>,    // defaults to 1
+        outcomes : Option<Vec<String>>,     // outcome names, defaults to StockA and StockB
+        liquidity : Option<u64>,    // LMSR liquidity parameter, the creator funds the market maker's worst case loss
+        resp: oneshot::Sender<Result<String, ExchangeError>>
     },
     SplitStocks {
         username: String,
         market_id: String,
-        amount: u64, // Amount of user balance to lock up
-        resp: oneshot::Sender<Result<String, String>>,
+        amount: u64, // Number of full sets to mint (one share of every outcome), each locks PAIR_PAYOUT of balance
+        resp: oneshot::Sender<Result<String, ExchangeError>>,
     },
     MergeStocks {
         username: String,
         market_id: String,
-        amount: u64, // Amount of YES+NO pairs to burn
-        resp: oneshot::Sender<Result<String, String>>,
+        amount: u64, // Number of full sets to burn, each pays back PAIR_PAYOUT
+        resp: oneshot::Sender<Result<String, ExchangeError>>,
     },
     UserDetails{
         username : String ,
-        resp: oneshot::Sender<Result<UserDetails, String>>,
+        resp: oneshot::Sender<Result<UserDetails, ExchangeError>>,
     },
     GetOrderbook{
         market_id : String,
-        resp: oneshot::Sender<Result<Orderbooks, String>>,
+        resp: oneshot::Sender<Result<Orderbooks, ExchangeError>>,
+    },
+    GetOpenOrders{
+        username : String,
+        resp: oneshot::Sender<Result<Vec<OpenOrder>, ExchangeError>>,
+    },
+    GetDepth{
+        market_id : String,
+        levels : usize,
+        resp: oneshot::Sender<Result<MarketDepth, ExchangeError>>,
+    },
+    GetTicker{
+        market_id : String,
+        resp: oneshot::Sender<Result<Ticker, ExchangeError>>,
+    },
+    AmendOrder{
+        username : String,
+        order_id : String,
+        price : Option<u64>,    // None keeps the current price
+        quantity : Option<u64>, // None keeps the current quantity
+        resp: oneshot::Sender<Result<OrderResult, ExchangeError>>,
+    },
+    CancelOrder{
+        username : String,
+        order_id : String,
+        resp: oneshot::Sender<Result<String, ExchangeError>>,
+    },
+    ResolveMarket{
+        username : String,
+        market_id : String,
+        winning_outcome : WinningOutcome,
+        resp: oneshot::Sender<Result<String, ExchangeError>>,
+    },
+    GetLedger{
+        username : String,
+        resp: oneshot::Sender<Result<Vec<JournalEntry>, ExchangeError>>,
+    },
+    CheckSolvency{
+        resp: oneshot::Sender<Result<SolvencyReport, ExchangeError>>,
+    },
+    Deposit{
+        username : String,
+        amount : u64,
+        resp: oneshot::Sender<Result<Deposit, ExchangeError>>,
+    },
+    Withdraw{
+        username : String,
+        amount : u64,
+        resp: oneshot::Sender<Result<Withdrawal, ExchangeError>>,
+    },
+    GetWithdrawals{
+        username : Option<String>,  // None lists every pending withdrawal, for admins
+        resp: oneshot::Sender<Result<Vec<Withdrawal>, ExchangeError>>,
+    },
+    DecideWithdrawal{
+        withdrawal_id : String,
+        decision : WithdrawalDecision,
+        resp: oneshot::Sender<Result<Withdrawal, ExchangeError>>,
+    },
+    GetPortfolio{
+        username : String,
+        resp: oneshot::Sender<Result<Portfolio, ExchangeError>>,
     }
 }
 
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct LimitOrderResponse{
+    pub order_id : String,
+    pub msg : String
+}
+
 #[derive(Debug , Clone , Serialize)]
 pub struct UserDetails{
-    pub balance : u64,
-    pub holdings : HashMap<String ,UserHoldings > 
+    pub balance : u64,              // available
+    pub reserved_balance : u64,     // locked by open buy orders
+    pub pending_withdrawal : u64,   // held by withdrawals waiting for approval
+    pub holdings : HashMap<String ,UserHoldings >,
+    pub reservations : HashMap<String, Reservation>    // order_id → what each open order has locked
+}
+
+// one outcome a user holds or has traded, marked to the mid of its book
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct PortfolioPosition{
+    pub market_id : String,
+    pub stock_type : StockType,
+    pub outcome : String,
+    pub quantity : u64,                 // available plus reserved
+    pub average_price : Option<f64>,
+    pub cost : u64,
+    pub mark_price : Option<f64>,       // None while either side of the book is empty
+    pub realised_pnl : i64,
+    pub unrealised_pnl : Option<f64>
+}
+
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct Portfolio{
+    pub positions : Vec<PortfolioPosition>,
+    pub realised_pnl : i64,
+    pub unrealised_pnl : f64            // of the positions that have a mark price
+}
+
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct MarketDepth{
+    pub outcomes : Vec<String>,
+    pub books : Vec<BookDepth>     // one per outcome, in the order of `outcomes`
+}
+
+// top of each book, prices of its own resting orders only
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct OutcomeTicker{
+    pub best_bid : Option<u64>,
+    pub best_ask : Option<u64>,
+    pub spread : Option<u64>,
+    pub mid : Option<f64>,
+    pub last_price : Option<f64>,   // exact average of a market maker trade
+    pub amm_price : Option<f64>     // marginal price of the market maker, if the market has one
+}
+
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct Ticker{
+    pub outcomes : Vec<String>,
+    pub tickers : Vec<OutcomeTicker>   // one per outcome, in the order of `outcomes`
 }
 
 #[derive(Debug , Serialize )]
 pub struct Orderbooks{
-    pub stock_a : OrderBook,
-    pub stock_b : OrderBook
+    pub outcomes : Vec<String>,
+    pub books : Vec<PublicOrderBook>   // one per outcome, in the order of `outcomes`
 }
\ No newline at end of file
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{Account, Asset, Ledger, Market, PAIR_PAYOUT, StockType, User};
+
+// Result of checking that the exchange holds exactly what it owes. Every violation is described in
+// `violations`, an empty list means the books balance.
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct SolvencyReport {
+    pub solvent : bool,
+    pub deposits : i128,        // net money that entered through funding
+    pub user_funds : i128,      // available, reserved and pending withdrawal balances of every user
+    pub market_funds : i128,    // collateral plus market maker cash of every market
+    pub violations : Vec<String>
+}
+
+// Checks the global invariants over the worker's state:
+// - all money is accounted for: user funds plus market funds equal the net deposits
+// - every outcome of an open market has as many shares outstanding, held by users (available or
+//   locked by sell orders) or the market maker, as its collateral backs full sets
+// - every open order's lock is the user's reservation for it, and the reservations add up to what
+//   the user has reserved
+// - the ledger agrees with every balance, holding and pool, and every position with the holdings
+pub fn solvency_report(users : &HashMap<String, User> , markets : &HashMap<String, Market> , ledger : &Ledger) -> SolvencyReport {
+    let mut violations = Vec::new();
+    let balances = ledger.balances();
+    let posted = |account : Account , asset : Asset| balances.get(&(account, asset)).copied().unwrap_or(0);
+
+    let deposits = -posted(Account::Funding, Asset::Cash);
+    let user_funds = users.values().map(|user| user.balance as i128 + user.reserved_balance as i128 + user.pending_withdrawal as i128).sum::<i128>();
+    let market_funds = markets.values().map(|market| market.collateral as i128 + market.amm.as_ref().map_or(0, |amm| amm.cash as i128)).sum::<i128>();
+    if user_funds + market_funds != deposits {
+        violations.push(format!("user funds {} plus market funds {} do not equal deposits {}", user_funds, market_funds, deposits));
+    }
+
+    for market in markets.values() {
+        let market_id = &market.market_id;
+        if !market.is_settled && !market.collateral.is_multiple_of(PAIR_PAYOUT) {
+            violations.push(format!("market {}: collateral {} does not back whole sets", market_id, market.collateral));
+        }
+        // a settled market pays out every share, what is left of the collateral is rounding dust
+        let sets = if market.is_settled { 0 } else { market.collateral / PAIR_PAYOUT };
+        for outcome in (0..market.outcomes.len()).map(StockType) {
+            let held = users.values().filter_map(|user| user.holdings.get(market_id))
+                .map(|holdings| holdings.stock(&outcome) + holdings.reserved(&outcome)).sum::<u64>();
+            let inventory = market.amm.as_ref().map_or(0, |amm| amm.inventory[outcome.0]);
+            if held + inventory != sets {
+                violations.push(format!("market {}: outcome {} has {} shares outstanding for {} sets", market_id, outcome.0, held + inventory, sets));
+            }
+            let issued = -posted(Account::Issuance(market_id.clone()), Asset::shares(market_id, &outcome));
+            if issued != (held + inventory) as i128 {
+                violations.push(format!("market {}: ledger issued {} shares of outcome {}, {} are outstanding", market_id, issued, outcome.0, held + inventory));
+            }
+            if posted(Account::MarketMaker(market_id.clone()), Asset::shares(market_id, &outcome)) != inventory as i128 {
+                violations.push(format!("market {}: ledger disagrees with the market maker's inventory of outcome {}", market_id, outcome.0));
+            }
+        }
+        if posted(Account::Collateral(market_id.clone()), Asset::Cash) != market.collateral as i128 {
+            violations.push(format!("market {}: ledger disagrees with the collateral of {}", market_id, market.collateral));
+        }
+        if posted(Account::MarketMaker(market_id.clone()), Asset::Cash) != market.amm.as_ref().map_or(0, |amm| amm.cash as i128) {
+            violations.push(format!("market {}: ledger disagrees with the market maker's cash", market_id));
+        }
+    }
+
+    for user in users.values() {
+        let username = &user.username;
+        let (available, reserved) = (Account::Available(username.clone()), Account::Reserved(username.clone()));
+        let mut open_orders = 0;
+        for market in markets.values() {
+            for order in market.open_orders(username) {
+                open_orders += 1;
+                let reservation = user.reservations.get(&order.order_id).map_or((0, 0), |reservation| (reservation.balance, reservation.stock));
+                if reservation != (order.locked_balance, order.locked_stock) {
+                    violations.push(format!("user {}: order {} locks {:?} but reserved {:?}", username, order.order_id, (order.locked_balance, order.locked_stock), reservation));
+                }
+            }
+            let holdings = user.holdings.get(&market.market_id).cloned().unwrap_or_default();
+            for outcome in (0..market.outcomes.len()).map(StockType) {
+                let locked = user.reservations.values().filter(|reservation| reservation.market_id == market.market_id && reservation.stock_type == outcome)
+                    .map(|reservation| reservation.stock).sum::<u64>();
+                if locked != holdings.reserved(&outcome) {
+                    violations.push(format!("user {}: reserved {} shares of outcome {} in market {}, orders lock {}", username, holdings.reserved(&outcome), outcome.0, market.market_id, locked));
+                }
+                let quantity = user.positions.get(&market.market_id).and_then(|positions| positions.get(outcome.0)).map_or(0, |position| position.quantity);
+                if quantity != holdings.stock(&outcome) + holdings.reserved(&outcome) {
+                    violations.push(format!("user {}: position of {} shares of outcome {} in market {} does not match the holdings", username, quantity, outcome.0, market.market_id));
+                }
+                let shares = Asset::shares(&market.market_id, &outcome);
+                if posted(available.clone(), shares.clone()) != holdings.stock(&outcome) as i128 || posted(reserved.clone(), shares) != holdings.reserved(&outcome) as i128 {
+                    violations.push(format!("user {}: ledger disagrees with the holdings of outcome {} in market {}", username, outcome.0, market.market_id));
+                }
+            }
+        }
+        if open_orders != user.reservations.len() {
+            violations.push(format!("user {}: {} reservations for {} open orders", username, user.reservations.len(), open_orders));
+        }
+        if user.reservations.values().map(|reservation| reservation.balance).sum::<u64>() != user.reserved_balance {
+            violations.push(format!("user {}: reserved balance {} is not what the orders lock", username, user.reserved_balance));
+        }
+        if posted(available, Asset::Cash) != user.balance as i128 || posted(reserved, Asset::Cash) != user.reserved_balance as i128
+            || posted(Account::PendingWithdrawal(username.clone()), Asset::Cash) != user.pending_withdrawal as i128 {
+            violations.push(format!("user {}: ledger disagrees with the balance", username));
+        }
+    }
+
+    SolvencyReport {
+        solvent : violations.is_empty(),
+        deposits,
+        user_funds,
+        market_funds,
+        violations
+    }
+}
@@ -2,26 +2,130 @@ use std::collections::HashMap;
 
 use serde::Serialize;
 
+use crate::{Account, Asset, ExchangeError, Ledger, Position, Reason, Reference, StockType};
+
 #[derive(Debug)]
 pub struct User {
     pub username : String ,
-    pub password : String , 
-    pub balance: u64,
+    pub password : String ,
+    pub balance: u64,           // available, open orders hold the rest in `reserved_balance`
+    pub reserved_balance : u64,
+    pub pending_withdrawal : u64,   // held by withdrawals waiting for approval
     pub holdings: HashMap<String, UserHoldings>, // market_id → holdings in that market
+    pub reservations : HashMap<String, Reservation>,  // order_id → what the order has locked
+    pub positions : HashMap<String, Vec<Position>>  // market_id → cost basis and PnL of every outcome, kept after settlement
 }
 impl User {
     pub fn get_holdings(&self, market_id: &str) -> UserHoldings {
     self.holdings.get(market_id).cloned().unwrap_or_default()
 }
+    pub fn position_mut(&mut self , market_id : &str , stock_type : &StockType) -> &mut Position {
+        let positions = self.positions.entry(market_id.to_string()).or_default();
+        if positions.len() <= stock_type.0 {
+            positions.resize(stock_type.0 + 1, Position::default());
+        }
+        &mut positions[stock_type.0]
+    }
+    // Moves `balance` (buy) or `stock` shares (sell) from available to reserved for an order. An order
+    // can reserve more later on, e.g. when it is amended.
+    pub fn reserve(&mut self , ledger : &mut Ledger , order_id : &str , market_id : &str , stock_type : &StockType , balance : u64 , stock : u64) -> Result<(), ExchangeError> {
+        if self.balance < balance {
+            return Err(ExchangeError::InsufficientFunds { required : balance, available : self.balance });
+        }
+        if stock > 0 {
+            let holdings = self.holdings.entry(market_id.to_string()).or_default();
+            let available = holdings.stock_mut(stock_type);
+            if *available < stock {
+                return Err(ExchangeError::InsufficientStock { required : stock, available : *available });
+            }
+            *available -= stock;
+            *holdings.reserved_mut(stock_type) += stock;
+        }
+        self.balance -= balance;
+        self.reserved_balance += balance;
+        let reservation = self.reservations.entry(order_id.to_string()).or_insert_with(|| Reservation {
+            market_id : market_id.to_string(),
+            stock_type : stock_type.clone(),
+            balance : 0,
+            stock : 0
+        });
+        reservation.balance += balance;
+        reservation.stock += stock;
+        self.post_reservation(ledger, Reason::OrderReserved, order_id, market_id, stock_type, balance, stock);
+        Ok(())
+    }
+    // gives part of an order's reservation back, e.g. when it is cancelled or filled below its limit
+    pub fn release(&mut self , ledger : &mut Ledger , reason : Reason , order_id : &str , balance : u64 , stock : u64) {
+        if let Some((market_id, stock_type)) = self.unreserve(order_id, balance, stock) {
+            self.balance += balance;
+            if stock > 0 {
+                *self.holdings.entry(market_id.clone()).or_default().stock_mut(&stock_type) += stock;
+            }
+            self.post_reservation(ledger, reason, order_id, &market_id, &stock_type, balance, stock);
+        }
+    }
+    // records a move between the user's own available and reserved accounts
+    #[allow(clippy::too_many_arguments)]
+    fn post_reservation(&self , ledger : &mut Ledger , reason : Reason , order_id : &str , market_id : &str , stock_type : &StockType , balance : u64 , stock : u64) {
+        let (available, reserved) = (Account::Available(self.username.clone()), Account::Reserved(self.username.clone()));
+        let (debit, credit) = match reason {
+            Reason::OrderReserved => (reserved, available),
+            _ => (available, reserved),
+        };
+        ledger.post(debit.clone(), credit.clone(), Asset::Cash, balance, reason, Reference::order(market_id, order_id));
+        ledger.post(debit, credit, Asset::shares(market_id, stock_type), stock, reason, Reference::order(market_id, order_id));
+    }
+    // takes part of an order's reservation for good, once it paid for or handed over a fill
+    pub fn consume(&mut self , order_id : &str , balance : u64 , stock : u64) {
+        self.unreserve(order_id, balance, stock);
+    }
+    fn unreserve(&mut self , order_id : &str , balance : u64 , stock : u64) -> Option<(String, StockType)> {
+        let reservation = self.reservations.get_mut(order_id)?;
+        reservation.balance -= balance;
+        reservation.stock -= stock;
+        let (market_id, stock_type) = (reservation.market_id.clone(), reservation.stock_type.clone());
+        if reservation.balance == 0 && reservation.stock == 0 {
+            self.reservations.remove(order_id);
+        }
+        self.reserved_balance -= balance;
+        if stock > 0 && let Some(holdings) = self.holdings.get_mut(&market_id) {
+            *holdings.reserved_mut(&stock_type) -= stock;
+        }
+        Some((market_id, stock_type))
+    }
+}
+// funds or shares set aside for one open order
+#[derive(Debug , Clone , PartialEq , Serialize)]
+pub struct Reservation {
+    pub market_id : String,
+    pub stock_type : StockType,
+    pub balance : u64,      // locked by a buy
+    pub stock : u64         // shares of `stock_type` locked by a sell
 }
+// shares held of every outcome, indexed by StockType
 #[derive(Debug,Default , Clone , Serialize)]
 pub struct UserHoldings {
-    pub stock_a : u64,
-    pub stock_b : u64
+    #[serde(rename = "available")]
+    pub stocks : Vec<u64>,
+    pub reserved : Vec<u64>     // locked by open sell orders
 }
 impl UserHoldings{
-    pub fn new (&mut self){
-        self.stock_a = 0 ;
-        self.stock_b = 0;
+    pub fn stock (&self , stock_type : &StockType) -> u64 {
+        self.stocks.get(stock_type.0).copied().unwrap_or(0)
+    }
+    pub fn stock_mut (&mut self , stock_type : &StockType) -> &mut u64 {
+        if self.stocks.len() <= stock_type.0 {
+            self.stocks.resize(stock_type.0 + 1, 0);
+        }
+        &mut self.stocks[stock_type.0]
     }
-}
\ No newline at end of file
+    pub fn reserved (&self , stock_type : &StockType) -> u64 {
+        self.reserved.get(stock_type.0).copied().unwrap_or(0)
+    }
+    pub fn reserved_mut (&mut self , stock_type : &StockType) -> &mut u64 {
+        if self.reserved.len() <= stock_type.0 {
+            self.reserved.resize(stock_type.0 + 1, 0);
+        }
+        &mut self.reserved[stock_type.0]
+    }
+}
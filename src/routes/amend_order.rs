@@ -0,0 +1,30 @@
+use actix_web::{patch ,web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, ExchangeError, OrderResult, Request, auth_extractor::AuthenticatedUser};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct AmendOrderPayload {
+    price : Option<u64>,
+    quantity : Option<u64>
+}
+
+#[patch("/order/{order_id}")]
+pub async fn amend_order(data : web::Data<AppState> , path : web::Path<String> , payload : web::Json<AmendOrderPayload> , username : AuthenticatedUser) -> impl Responder {
+    let (tx , rx) = oneshot::channel::<Result<OrderResult,ExchangeError>>();
+    let req = Request::AmendOrder { 
+        username: username.username, 
+        order_id : path.into_inner(),
+        price : payload.price,
+        quantity : payload.quantity,
+        resp: tx
+    };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(result)) => HttpResponse::Ok().json(result),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
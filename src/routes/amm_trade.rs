@@ -0,0 +1,38 @@
+use actix_web::{post ,web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::auth_extractor::AuthenticatedUser;
+use crate::{AppState, ExchangeError, Request};
+use serde::Deserialize;
+use crate::order::*;
+
+#[derive(Deserialize)]
+struct AmmTradePayload {
+    stock_type : StockType,
+    quantity : u64,
+    ordertype : Ordertype,
+    market_id : String,
+    #[serde(default)]
+    limit : Option<u64>     // most a buy pays or least a sell receives in total
+}
+
+#[post("/amm/trade")]
+pub async fn amm_trade(data : web::Data<AppState> , payload : web::Json<AmmTradePayload>,  username : AuthenticatedUser) -> impl Responder {
+    let (tx , rx) = oneshot::channel::<Result<AmmTradeResult,ExchangeError>>();
+    let req = Request::TradeAmm { 
+        username: username.username, 
+        stock_type: payload.stock_type.clone(), 
+        quantity: payload.quantity,
+        ordertype: payload.ordertype.clone(), 
+        market_id : payload.market_id.clone(),
+        limit : payload.limit,
+        resp: tx
+    };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(result)) => HttpResponse::Ok().json(result),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
\ No newline at end of file
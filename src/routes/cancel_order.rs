@@ -0,0 +1,21 @@
+use actix_web::{delete ,web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, ExchangeError, Request, auth_extractor::AuthenticatedUser};
+
+#[delete("/order/{order_id}")]
+pub async fn cancel_order(data : web::Data<AppState> , path : web::Path<String> , username : AuthenticatedUser) -> impl Responder {
+    let (tx , rx) = oneshot::channel::<Result<String,ExchangeError>>();
+    let req = Request::CancelOrder { 
+        username: username.username, 
+        order_id : path.into_inner(),
+        resp: tx
+    };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(msg)) => HttpResponse::Ok().body(msg),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
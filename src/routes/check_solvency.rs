@@ -0,0 +1,21 @@
+use actix_web::{get , web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, ExchangeError, Request, SolvencyReport, auth::is_admin, auth_extractor::AuthenticatedUser};
+
+// runs the solvency checks on demand, the report lists every violation found
+#[get("/admin/solvency")]
+pub async fn check_solvency(data : web::Data<AppState> , username : AuthenticatedUser) -> impl Responder {
+    if !is_admin(&username.username) {
+        return ExchangeError::NotAdmin.error_response();
+    }
+    let (tx ,  rx) = oneshot::channel::<Result<SolvencyReport,ExchangeError>>();
+    let req = Request::CheckSolvency { resp: tx };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(report)) => HttpResponse::Ok().json(report),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
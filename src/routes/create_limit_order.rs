@@ -1,6 +1,6 @@
-use actix_web::{post ,web, HttpResponse, Responder};
+use actix_web::{post ,web, HttpResponse, Responder, ResponseError};
 use tokio::sync::oneshot;
-use crate::{AppState, Request, auth_extractor::AuthenticatedUser};
+use crate::{AppState, ExchangeError, Request, auth_extractor::AuthenticatedUser};
 use serde::Deserialize;
 use crate::order::*;
 
@@ -10,12 +10,20 @@ struct OrderPayload {
     price : u64,
     quantity : u64,
     ordertype : Ordertype,
-    market_id : String
+    market_id : String,
+    #[serde(default)]
+    time_in_force : TimeInForce,
+    #[serde(default)]
+    post_only : PostOnly,
+    #[serde(default)]
+    self_trade_prevention : SelfTradePrevention,
+    #[serde(default)]
+    display_quantity : Option<u64>
 }
 
 #[post("/limitorder")]
 pub async fn create_limit_order(data : web::Data<AppState> , payload : web::Json<OrderPayload>  , username : AuthenticatedUser ) -> impl Responder {
-    let (tx , mut rx) = oneshot::channel::<Result<String,String>>();
+    let (tx , rx) = oneshot::channel::<Result<OrderResult,ExchangeError>>();
     let req = Request::CreateLimitOrder { 
         username : username.username, 
         stock_type: payload.stock_type.clone(), 
@@ -23,14 +31,18 @@ pub async fn create_limit_order(data : web::Data<AppState> , payload : web::Json
         quantity:payload.quantity,
         market_id : payload.market_id.clone(),
         ordertype: payload.ordertype.clone(), 
+        time_in_force: payload.time_in_force,
+        post_only: payload.post_only,
+        self_trade_prevention: payload.self_trade_prevention,
+        display_quantity: payload.display_quantity,
         resp: tx
     };
-    if let Err(_) = data.worker.send(req).await {
-        return HttpResponse::InternalServerError().body("Background worker creashed");
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
     }
     match rx.await {
-        Ok(Ok(msg)) => HttpResponse::Ok().body(msg),
-        Ok(Err(err)) => HttpResponse::BadRequest().body(err),
-        Err(_) => HttpResponse::InternalServerError().body("No response from worker"),
+        Ok(Ok(result)) => HttpResponse::Ok().json(result),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
     }
 }
\ No newline at end of file
@@ -1,28 +1,36 @@
-use actix_web::{post ,web, HttpResponse, Responder};
+use actix_web::{post ,web, HttpResponse, Responder, ResponseError};
 use tokio::sync::oneshot;
-use crate::{AppState, Request, auth_extractor::AuthenticatedUser};
+use crate::{AppState, ExchangeError, Request, auth_extractor::AuthenticatedUser};
 use serde::Deserialize;
-use crate::order::*;
 
 #[derive(Deserialize)]
 struct CreateMarketPayload {
-    market_name : String
+    market_name : String,
+    #[serde(default)]
+    tick_size : Option<u64>,
+    #[serde(default)]
+    outcomes : Option<Vec<String>>,
+    #[serde(default)]
+    liquidity : Option<u64>     // funds an LMSR market maker when set
 }
 
 #[post("/create_market")]
 pub async fn create_market(data : web::Data<AppState> , payload : web::Json<CreateMarketPayload> , username : AuthenticatedUser) -> impl Responder {
-    let (tx , mut rx) = oneshot::channel::<Result<String,String>>();
+    let (tx , rx) = oneshot::channel::<Result<String,ExchangeError>>();
     let req = Request::CreateMarket { 
         username: username.username, 
         market_name : payload.market_name.clone(),
+        tick_size : payload.tick_size,
+        outcomes : payload.outcomes.clone(),
+        liquidity : payload.liquidity,
         resp: tx
     };
-    if let Err(_) = data.worker.send(req).await {
-        return HttpResponse::InternalServerError().body("Background worker creashed");
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
     }
     match rx.await {
         Ok(Ok(msg)) => HttpResponse::Ok().body(msg),
-        Ok(Err(err)) => HttpResponse::BadRequest().body(err),
-        Err(_) => HttpResponse::InternalServerError().body("No response from worker"),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
     }
 }
\ No newline at end of file
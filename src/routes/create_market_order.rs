@@ -1,10 +1,9 @@
-use actix_web::{post ,web, HttpResponse, Responder};
+use actix_web::{post ,web, HttpResponse, Responder, ResponseError};
 use tokio::sync::oneshot;
 use crate::auth_extractor::AuthenticatedUser;
-use crate::{AppState, Request};
+use crate::{AppState, ExchangeError, Request};
 use serde::Deserialize;
 use crate::order::*;
-use crate::request::*;
 
 #[derive(Deserialize)]
 struct MarketOrderPayload {
@@ -12,26 +11,35 @@ struct MarketOrderPayload {
     stock_type : StockType , // Option A or Option B (yes or no)
     quantity : u64,
     ordertype : Ordertype,
-    market_id : String
+    market_id : String,
+    #[serde(default)]
+    self_trade_prevention : SelfTradePrevention,
+    #[serde(flatten)]
+    limits : SlippageLimits,    // optional max_price, min_price and max_total_cost
+    #[serde(default)]
+    all_or_nothing : bool
 }
 
 #[post("/marketorder")]
 pub async fn create_market_order(data : web::Data<AppState> , payload : web::Json<MarketOrderPayload>,  username : AuthenticatedUser) -> impl Responder {
-    let (tx , mut rx) = oneshot::channel::<Result<String,String>>();
+    let (tx , rx) = oneshot::channel::<Result<OrderResult,ExchangeError>>();
     let req = Request::CreateMarketOrder { 
         username: username.username, 
         stock_type: payload.stock_type.clone(), 
         quantity:payload.quantity,
         ordertype: payload.ordertype.clone(), 
         market_id : payload.market_id.clone(),
+        self_trade_prevention : payload.self_trade_prevention,
+        limits : payload.limits,
+        all_or_nothing : payload.all_or_nothing,
         resp: tx
     };
-    if let Err(_) = data.worker.send(req).await {
-        return HttpResponse::InternalServerError().body("Background worker creashed");
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
     }
     match rx.await {
-        Ok(Ok(msg)) => HttpResponse::Ok().body(msg),
-        Ok(Err(err)) => HttpResponse::BadRequest().body(err),
-        Err(_) => HttpResponse::InternalServerError().body("No response from worker"),
+        Ok(Ok(result)) => HttpResponse::Ok().json(result),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
     }
 }
\ No newline at end of file
@@ -0,0 +1,42 @@
+use actix_web::{post ,web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, ExchangeError, LimitOrderResponse, Request, auth_extractor::AuthenticatedUser};
+use serde::Deserialize;
+use crate::order::*;
+
+#[derive(Deserialize)]
+struct StopOrderPayload {
+    stock_type : StockType , // Option A or Option B (yes or no)
+    quantity : u64,
+    ordertype : Ordertype,
+    market_id : String,
+    trigger_price : u64,
+    #[serde(default)]
+    limit_price : Option<u64>,  // makes it a stop-limit order
+    #[serde(default)]
+    self_trade_prevention : SelfTradePrevention
+}
+
+#[post("/stoporder")]
+pub async fn create_stop_order(data : web::Data<AppState> , payload : web::Json<StopOrderPayload>  , username : AuthenticatedUser ) -> impl Responder {
+    let (tx , rx) = oneshot::channel::<Result<LimitOrderResponse,ExchangeError>>();
+    let req = Request::CreateStopOrder { 
+        username : username.username, 
+        stock_type: payload.stock_type.clone(), 
+        quantity: payload.quantity,
+        ordertype: payload.ordertype.clone(), 
+        market_id : payload.market_id.clone(),
+        trigger_price: payload.trigger_price,
+        limit_price: payload.limit_price,
+        self_trade_prevention: payload.self_trade_prevention,
+        resp: tx
+    };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(msg)) => HttpResponse::Ok().json(msg),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
@@ -0,0 +1,31 @@
+use actix_web::{post ,web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, ExchangeError, Request, Withdrawal, WithdrawalDecision, auth::is_admin, auth_extractor::AuthenticatedUser};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct DecideWithdrawalPayload {
+    decision : WithdrawalDecision
+}
+
+// approving pays the withdrawal out through the funding source, rejecting gives it back to the user
+#[post("/admin/withdrawals/{withdrawal_id}")]
+pub async fn decide_withdrawal(data : web::Data<AppState> , path : web::Path<String> , payload : web::Json<DecideWithdrawalPayload> , username : AuthenticatedUser) -> impl Responder {
+    if !is_admin(&username.username) {
+        return ExchangeError::NotAdmin.error_response();
+    }
+    let (tx , rx) = oneshot::channel::<Result<Withdrawal,ExchangeError>>();
+    let req = Request::DecideWithdrawal { 
+        withdrawal_id : path.into_inner(),
+        decision : payload.decision,
+        resp: tx
+    };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(withdrawal)) => HttpResponse::Ok().json(withdrawal),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
@@ -0,0 +1,28 @@
+use actix_web::{post ,web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, Deposit, ExchangeError, Request, auth_extractor::AuthenticatedUser};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct DepositPayload {
+    amount : u64
+}
+
+// pulls money in through the funding source and credits it to the caller
+#[post("/deposit")]
+pub async fn deposit(data : web::Data<AppState> , payload : web::Json<DepositPayload> , username : AuthenticatedUser) -> impl Responder {
+    let (tx , rx) = oneshot::channel::<Result<Deposit,ExchangeError>>();
+    let req = Request::Deposit { 
+        username: username.username, 
+        amount : payload.amount,
+        resp: tx
+    };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(deposit)) => HttpResponse::Ok().json(deposit),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
@@ -0,0 +1,21 @@
+use actix_web::{get , web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, ExchangeError, JournalEntry, Request, auth_extractor::AuthenticatedUser};
+
+// every journal entry that moved the caller's cash or shares, oldest first
+#[get("/ledger")]
+pub async fn get_ledger(data : web::Data<AppState> , username : AuthenticatedUser) -> impl Responder {
+    let (tx ,  rx) = oneshot::channel::<Result<Vec<JournalEntry>,ExchangeError>>();
+    let req = Request::GetLedger { 
+        username: username.username, 
+        resp: tx 
+    };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(entries)) => HttpResponse::Ok().json(entries),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
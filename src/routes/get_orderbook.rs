@@ -1,6 +1,6 @@
-use actix_web::{get ,web, HttpResponse, Responder};
+use actix_web::{get ,web, HttpResponse, Responder, ResponseError};
 use tokio::sync::oneshot;
-use crate::{AppState, Orderbooks, Request, auth_extractor::AuthenticatedUser};
+use crate::{AppState, ExchangeError, Orderbooks, Request, auth_extractor::AuthenticatedUser};
 use serde::Deserialize;
 
 #[derive(Deserialize)]
@@ -9,18 +9,18 @@ struct GetOrderbookPayload {
 }
 
 #[get("/get_orderbook")]
-pub async fn get_orderbook(data : web::Data<AppState> , payload : web::Json<GetOrderbookPayload> , username : AuthenticatedUser) -> impl Responder {
-    let (tx ,  rx) = oneshot::channel::<Result<Orderbooks,String>>();
+pub async fn get_orderbook(data : web::Data<AppState> , payload : web::Json<GetOrderbookPayload> , _username : AuthenticatedUser) -> impl Responder {
+    let (tx ,  rx) = oneshot::channel::<Result<Orderbooks,ExchangeError>>();
     let req = Request::GetOrderbook { 
         market_id: payload.market_id.to_string(), 
         resp: tx
     } ;
-    if let Err(_) = data.worker.send(req).await {
-        return HttpResponse::InternalServerError().body("Background worker creashed");
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
     }
     match rx.await {
         Ok(Ok(msg)) => HttpResponse::Ok().json(msg),
-        Ok(Err(err)) => HttpResponse::BadRequest().body(err),
-        Err(_) => HttpResponse::InternalServerError().body("No response from worker"),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
     }
 }
\ No newline at end of file
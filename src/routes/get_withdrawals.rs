@@ -0,0 +1,21 @@
+use actix_web::{get , web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, ExchangeError, Request, Withdrawal, auth_extractor::AuthenticatedUser};
+
+// the caller's withdrawals in every state, oldest first
+#[get("/withdrawals")]
+pub async fn get_withdrawals(data : web::Data<AppState> , username : AuthenticatedUser) -> impl Responder {
+    let (tx ,  rx) = oneshot::channel::<Result<Vec<Withdrawal>,ExchangeError>>();
+    let req = Request::GetWithdrawals { 
+        username: Some(username.username), 
+        resp: tx 
+    };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(withdrawals)) => HttpResponse::Ok().json(withdrawals),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
@@ -0,0 +1,27 @@
+use actix_web::{get ,web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, ExchangeError, MarketDepth, Request, auth_extractor::AuthenticatedUser};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct DepthQuery {
+    levels : Option<usize>  // price levels per side, 10 by default
+}
+
+#[get("/markets/{market_id}/depth")]
+pub async fn market_depth(data : web::Data<AppState> , path : web::Path<String> , query : web::Query<DepthQuery> , _username : AuthenticatedUser) -> impl Responder {
+    let (tx ,  rx) = oneshot::channel::<Result<MarketDepth,ExchangeError>>();
+    let req = Request::GetDepth { 
+        market_id: path.into_inner(), 
+        levels : query.levels.unwrap_or(10),
+        resp: tx
+    } ;
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(depth)) => HttpResponse::Ok().json(depth),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
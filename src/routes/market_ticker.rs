@@ -0,0 +1,20 @@
+use actix_web::{get ,web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, ExchangeError, Request, Ticker, auth_extractor::AuthenticatedUser};
+
+#[get("/markets/{market_id}/ticker")]
+pub async fn market_ticker(data : web::Data<AppState> , path : web::Path<String> , _username : AuthenticatedUser) -> impl Responder {
+    let (tx ,  rx) = oneshot::channel::<Result<Ticker,ExchangeError>>();
+    let req = Request::GetTicker { 
+        market_id: path.into_inner(), 
+        resp: tx
+    } ;
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(ticker)) => HttpResponse::Ok().json(ticker),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
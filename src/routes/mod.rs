@@ -13,4 +13,38 @@ pub use split_stocks::*;
 pub mod merge;
 pub use merge::*;
 pub mod user_details;
-pub mod get_orderbook;
\ No newline at end of file
+pub mod get_orderbook;
+pub mod resolve_market;
+pub use resolve_market::*;
+pub mod cancel_order;
+pub use cancel_order::*;
+pub mod amend_order;
+pub use amend_order::*;
+pub mod quote;
+pub use quote::*;
+pub mod create_stop_order;
+pub use create_stop_order::*;
+pub mod market_depth;
+pub use market_depth::*;
+pub mod market_ticker;
+pub use market_ticker::*;
+pub mod open_orders;
+pub use open_orders::*;
+pub mod amm_trade;
+pub use amm_trade::*;
+pub mod get_ledger;
+pub use get_ledger::*;
+pub mod check_solvency;
+pub use check_solvency::*;
+pub mod deposit;
+pub use deposit::*;
+pub mod withdraw;
+pub use withdraw::*;
+pub mod get_withdrawals;
+pub use get_withdrawals::*;
+pub mod pending_withdrawals;
+pub use pending_withdrawals::*;
+pub mod decide_withdrawal;
+pub use decide_withdrawal::*;
+pub mod portfolio;
+pub use portfolio::*;
\ No newline at end of file
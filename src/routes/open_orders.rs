@@ -0,0 +1,21 @@
+use actix_web::{get , web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, ExchangeError, OpenOrder, Request, auth_extractor::AuthenticatedUser};
+
+// the caller's own resting and pending stop orders across all markets
+#[get("/orders/open")]
+pub async fn open_orders(data : web::Data<AppState> , username : AuthenticatedUser) -> impl Responder {
+    let (tx ,  rx) = oneshot::channel::<Result<Vec<OpenOrder>,ExchangeError>>();
+    let req = Request::GetOpenOrders { 
+        username: username.username, 
+        resp: tx 
+    };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(orders)) => HttpResponse::Ok().json(orders),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
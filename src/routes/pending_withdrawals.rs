@@ -0,0 +1,21 @@
+use actix_web::{get , web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, ExchangeError, Request, Withdrawal, auth::is_admin, auth_extractor::AuthenticatedUser};
+
+// every user's withdrawals waiting for a decision, oldest first
+#[get("/admin/withdrawals")]
+pub async fn pending_withdrawals(data : web::Data<AppState> , username : AuthenticatedUser) -> impl Responder {
+    if !is_admin(&username.username) {
+        return ExchangeError::NotAdmin.error_response();
+    }
+    let (tx ,  rx) = oneshot::channel::<Result<Vec<Withdrawal>,ExchangeError>>();
+    let req = Request::GetWithdrawals { username: None, resp: tx };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(withdrawals)) => HttpResponse::Ok().json(withdrawals),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
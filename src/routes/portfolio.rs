@@ -0,0 +1,21 @@
+use actix_web::{get , web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, ExchangeError, Portfolio, Request, auth_extractor::AuthenticatedUser};
+
+// the caller's positions with their average cost and realised and unrealised PnL
+#[get("/portfolio")]
+pub async fn portfolio(data : web::Data<AppState> , username : AuthenticatedUser) -> impl Responder {
+    let (tx ,  rx) = oneshot::channel::<Result<Portfolio,ExchangeError>>();
+    let req = Request::GetPortfolio { 
+        username: username.username, 
+        resp: tx 
+    };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(portfolio)) => HttpResponse::Ok().json(portfolio),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
@@ -0,0 +1,49 @@
+use actix_web::{get ,web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::auth_extractor::AuthenticatedUser;
+use crate::{AppState, ExchangeError, Request};
+use serde::Deserialize;
+use crate::order::*;
+
+// The market order payload as query parameters. Query strings carry every value as text, so the
+// outcome is parsed by hand and the slippage limits are listed flat.
+#[derive(Deserialize)]
+struct QuoteQuery {
+    stock_type : String,    // outcome index or legacy StockA / StockB name
+    quantity : u64,
+    ordertype : Ordertype,
+    market_id : String,
+    #[serde(default)]
+    self_trade_prevention : SelfTradePrevention,
+    max_price : Option<u64>,
+    min_price : Option<u64>,
+    max_total_cost : Option<u64>
+}
+
+// what a market order with the same parameters would fill right now, nothing is traded
+#[get("/quote")]
+pub async fn quote(data : web::Data<AppState> , query : web::Query<QuoteQuery>,  username : AuthenticatedUser) -> impl Responder {
+    let stock_type = match query.stock_type.parse::<StockType>() {
+        Ok(stock_type) => stock_type,
+        Err(err) => return err.error_response(),
+    };
+    let (tx , rx) = oneshot::channel::<Result<Quote,ExchangeError>>();
+    let req = Request::QuoteMarketOrder { 
+        username: username.username, 
+        stock_type,
+        quantity: query.quantity,
+        ordertype: query.ordertype.clone(), 
+        market_id : query.market_id.clone(),
+        self_trade_prevention : query.self_trade_prevention,
+        limits : SlippageLimits { max_price : query.max_price, min_price : query.min_price, max_total_cost : query.max_total_cost },
+        resp: tx
+    };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(quote)) => HttpResponse::Ok().json(quote),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
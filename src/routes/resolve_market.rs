@@ -0,0 +1,29 @@
+use actix_web::{post ,web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, ExchangeError, Request, WinningOutcome, auth_extractor::AuthenticatedUser};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct ResolveMarketPayload {
+    market_id : String,
+    winning_outcome : WinningOutcome
+}
+
+#[post("/resolve_market")]
+pub async fn resolve_market(data : web::Data<AppState> , payload : web::Json<ResolveMarketPayload> , username : AuthenticatedUser) -> impl Responder {
+    let (tx , rx) = oneshot::channel::<Result<String,ExchangeError>>();
+    let req = Request::ResolveMarket { 
+        username: username.username, 
+        market_id : payload.market_id.clone(),
+        winning_outcome : payload.winning_outcome,
+        resp: tx
+    };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(msg)) => HttpResponse::Ok().body(msg),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
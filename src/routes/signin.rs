@@ -1,6 +1,6 @@
-use actix_web::{post ,web, HttpResponse, Responder};
+use actix_web::{post ,web, HttpResponse, Responder, ResponseError};
 use tokio::sync::oneshot;
-use crate::{AppState, Request};
+use crate::{AppState, ExchangeError, Request};
 use serde::Deserialize;
 use crate::auth::*;
 
@@ -17,14 +17,14 @@ pub struct AuthResponse {
 
 #[post("/signin")]
 pub async fn signin (data : web::Data<AppState> , payload : web::Json<SigninPayload>) -> impl Responder {
-    let (tx , rx) = oneshot::channel::<Result<String,String>>();
+    let (tx , rx) = oneshot::channel::<Result<String,ExchangeError>>();
     let req = Request::Signin { 
         username: payload.username.clone(), 
         password: payload.password.clone(), 
         resp: tx 
     };
-    if let Err(_) = data.worker.send(req).await {
-        return HttpResponse::InternalServerError().body("Background worker creashed");
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
     }
     match rx.await {
         Ok(Ok(msg)) => {
@@ -34,7 +34,7 @@ pub async fn signin (data : web::Data<AppState> , payload : web::Json<SigninPayl
                 Err(_) => HttpResponse::InternalServerError().body("Error in signing in")
             }
         },
-        Ok(Err(err)) => HttpResponse::BadRequest().body(err),
-        Err(_) => HttpResponse::InternalServerError().body("No response from worker"),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
     }
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
-use actix_web::{post , web, HttpResponse, Responder};
+use actix_web::{post , web, HttpResponse, Responder, ResponseError};
 use tokio::sync::oneshot;
-use crate::{AppState, Request};
+use crate::{AppState, ExchangeError, Request};
 use serde::Deserialize;
 use crate::hash::*;
 
@@ -12,19 +12,19 @@ pub struct SignupPayload{
 
 #[post("/signup")]
 pub async fn signup(data : web::Data<AppState> , payload : web::Json<SignupPayload>) -> impl Responder {
-    let (tx ,  rx) = oneshot::channel::<Result<String,String>>();
+    let (tx ,  rx) = oneshot::channel::<Result<String,ExchangeError>>();
     let req = Request::Signup { 
         username: payload.username.clone(), 
         password: hash_password(&payload.password), 
         resp: tx 
     };
-    if let Err(_) = data.worker.send(req).await {
-        return HttpResponse::InternalServerError().body("Background worker creashed");
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
     }
     match rx.await {
         Ok(Ok(msg)) => HttpResponse::Ok().body(msg),
-        Ok(Err(err)) => HttpResponse::BadRequest().body(err),
-        Err(_) => HttpResponse::InternalServerError().body("No response from worker"),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
     }
     
 }
\ No newline at end of file
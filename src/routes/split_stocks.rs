@@ -1,6 +1,6 @@
-use actix_web::{post ,web, HttpResponse, Responder};
+use actix_web::{post ,web, HttpResponse, Responder, ResponseError};
 use tokio::sync::oneshot;
-use crate::{AppState, Request, auth_extractor::AuthenticatedUser};
+use crate::{AppState, ExchangeError, Request, auth_extractor::AuthenticatedUser};
 use serde::Deserialize;
 
 #[derive(Deserialize)]
@@ -11,19 +11,19 @@ struct SplitStocks {
 
 #[post("/split_stocks")]
 pub async fn split_stocks(data : web::Data<AppState> , payload : web::Json<SplitStocks> , username : AuthenticatedUser) -> impl Responder {
-    let (tx ,  rx) = oneshot::channel::<Result<String,String>>();
+    let (tx ,  rx) = oneshot::channel::<Result<String,ExchangeError>>();
     let req = Request::SplitStocks  { 
         username: username.username, 
         market_id : payload.market_id.clone(),
         amount : payload.amount,
         resp: tx
     };
-    if let Err(_) = data.worker.send(req).await {
-        return HttpResponse::InternalServerError().body("Background worker creashed");
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
     }
     match rx.await {
         Ok(Ok(msg)) => HttpResponse::Ok().body(msg),
-        Ok(Err(err)) => HttpResponse::BadRequest().body(err),
-        Err(_) => HttpResponse::InternalServerError().body("No response from worker"),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
     }
 }
\ No newline at end of file
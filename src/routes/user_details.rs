@@ -1,22 +1,22 @@
-use actix_web::{get , web, HttpResponse, Responder};
+use actix_web::{get , web, HttpResponse, Responder, ResponseError};
 use tokio::sync::oneshot;
-use crate::{AppState, Request, UserDetails, auth_extractor::AuthenticatedUser};
+use crate::{AppState, ExchangeError, Request, UserDetails, auth_extractor::AuthenticatedUser};
 
 
 #[get("/user_details")]
 pub async fn user_details(data : web::Data<AppState> , username : AuthenticatedUser) -> impl Responder {
-    let (tx ,  rx) = oneshot::channel::<Result<UserDetails,String>>();
+    let (tx ,  rx) = oneshot::channel::<Result<UserDetails,ExchangeError>>();
     let req = Request::UserDetails  { 
         username: username.username, 
         resp: tx 
     };
-    if let Err(_) = data.worker.send(req).await {
-        return HttpResponse::InternalServerError().body("Background worker creashed");
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
     }
     match rx.await {
         Ok(Ok(msg)) => HttpResponse::Ok().json(msg),
-        Ok(Err(err)) => HttpResponse::BadRequest().body(err),
-        Err(_) => HttpResponse::InternalServerError().body("No response from worker"),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
     }
     
 }
\ No newline at end of file
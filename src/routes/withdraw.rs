@@ -0,0 +1,28 @@
+use actix_web::{post ,web, HttpResponse, Responder, ResponseError};
+use tokio::sync::oneshot;
+use crate::{AppState, ExchangeError, Request, Withdrawal, auth_extractor::AuthenticatedUser};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct WithdrawPayload {
+    amount : u64
+}
+
+// requests a payout, the amount is held until an admin approves or rejects it
+#[post("/withdraw")]
+pub async fn withdraw(data : web::Data<AppState> , payload : web::Json<WithdrawPayload> , username : AuthenticatedUser) -> impl Responder {
+    let (tx , rx) = oneshot::channel::<Result<Withdrawal,ExchangeError>>();
+    let req = Request::Withdraw { 
+        username: username.username, 
+        amount : payload.amount,
+        resp: tx
+    };
+    if data.worker.send(req).await.is_err() {
+        return ExchangeError::WorkerUnavailable.error_response();
+    }
+    match rx.await {
+        Ok(Ok(withdrawal)) => HttpResponse::Ok().json(withdrawal),
+        Ok(Err(err)) => err.error_response(),
+        Err(_) => ExchangeError::WorkerUnavailable.error_response(),
+    }
+}
@@ -1,4 +1,3 @@
-use actix_web::web::to;
 use jsonwebtoken::{encode, decode, Header, EncodingKey, DecodingKey, Validation};
 use serde::{Serialize, Deserialize};
 use chrono::{Utc, Duration};
@@ -11,7 +10,7 @@ pub struct Claims {
 
 pub fn create_jwt(username: &str) -> Result<String, jsonwebtoken::errors::Error> {
 
-    let JWT_SECRET = std::env::var("JWT_SECRET").unwrap_or_else(|_|"JWT".to_string() );
+    let jwt_secret = std::env::var("JWT_SECRET").unwrap_or_else(|_|"JWT".to_string() );
     let expiration = Utc::now().checked_add_signed(Duration::days(1)).expect("valid timestamp").timestamp();
 
     let claims = Claims {
@@ -19,12 +18,12 @@ pub fn create_jwt(username: &str) -> Result<String, jsonwebtoken::errors::Error>
         exp: expiration as usize,
     };
     
-    encode(&Header::default(), &claims, &EncodingKey::from_secret(JWT_SECRET.as_ref()))
+    encode(&Header::default(), &claims, &EncodingKey::from_secret(jwt_secret.as_ref()))
 }
 
 pub fn decode_jwt(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-    let JWT_SECRET = std::env::var("JWT_SECRET").unwrap_or_else(|_|"JWT".to_string() );
-    let token_data = decode::<Claims>(token, &DecodingKey::from_secret(JWT_SECRET.as_ref()), &Validation::default());
+    let jwt_secret = std::env::var("JWT_SECRET").unwrap_or_else(|_|"JWT".to_string() );
+    let token_data = decode::<Claims>(token, &DecodingKey::from_secret(jwt_secret.as_ref()), &Validation::default());
     match token_data {
         Ok(data) => {
             Ok(data.claims)
@@ -33,4 +32,9 @@ pub fn decode_jwt(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
             Err(err)
         }
     }
-}
\ No newline at end of file
+}
+
+// admins are named by the ADMIN_USERNAME environment variable, there are none without it
+pub fn is_admin(username: &str) -> bool {
+    std::env::var("ADMIN_USERNAME").is_ok_and(|admin| admin == username)
+}
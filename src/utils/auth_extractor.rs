@@ -1,4 +1,5 @@
-use std::{fs::read, future::{Ready, ready}};
+use std::future::{Ready, ready};
+use crate::ExchangeError;
 use crate::utils::auth::decode_jwt;
 use actix_web::{
     dev::Payload,
@@ -11,23 +12,23 @@ pub struct AuthenticatedUser {
 impl FromRequest for AuthenticatedUser {
     type Error = ActixError;
     type Future = Ready<Result<Self,Self::Error>>;
-    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
         let header = match req.headers().get("Authorization") {
             Some(header) => header,
             None =>{ 
-                return ready(Err(actix_web::error::ErrorUnauthorized("No token provided")));
+                return ready(Err(ExchangeError::Unauthorized("No token provided".to_string()).into()));
             }
         };
         let auth_str = match header.to_str() {
             Ok(auth_str) => auth_str,
             Err(_) => {
-                return  ready(Err(actix_web::error::ErrorUnauthorized("Invalid token format")));
+                return  ready(Err(ExchangeError::Unauthorized("Invalid token format".to_string()).into()));
             }
         };
         let vec : Vec<&str> = auth_str.split_whitespace().collect();
         if vec.len() != 2 || vec[0] != "Bearer" {
             // Header is not "Bearer <token>"
-            return ready(Err(actix_web::error::ErrorUnauthorized("Invalid token format")));
+            return ready(Err(ExchangeError::Unauthorized("Invalid token format".to_string()).into()));
         }
         let token = vec[1];
         match decode_jwt(token) {
@@ -36,7 +37,7 @@ impl FromRequest for AuthenticatedUser {
             }
             Err(_) => {
                 // Token is invalid (expired, wrong signature, etc.)
-                ready(Err(actix_web::error::ErrorUnauthorized("Invalid or expired token")))
+                ready(Err(ExchangeError::Unauthorized("Invalid or expired token".to_string()).into()))
             }
         }
     }
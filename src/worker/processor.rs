@@ -2,336 +2,925 @@
 use crate::models::*;
 use crate::utils::*;
 use chrono::Utc;
+use nanoid::nanoid;
 use std::collections::HashMap;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::mpsc;
+
+// What a worker starts with. Deposits and withdrawals are disabled until a funding source is
+// configured, the mock is only meant for tests.
+pub struct WorkerConfig {
+    pub starting_balance : u64,     // credited to every new user, may be zero
+    pub funding : Option<Box<dyn FundingSource + Send>>,
+    pub solvency_check_interval : u64,  // debug builds check the books after every this many requests
+    pub admin_username : Option<String>,    // reserved at signup, the account is only created from `admin_password`
+    pub admin_password : Option<String>     // plaintext, hashed when the admin account is created
+}
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig { starting_balance : 5000, funding : None, solvency_check_interval : 1, admin_username : None, admin_password : None }
+    }
+}
+impl WorkerConfig {
+    // reads the starting balance from STARTING_BALANCE and the admin account from ADMIN_USERNAME and
+    // ADMIN_PASSWORD
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let starting_balance = std::env::var("STARTING_BALANCE").ok().and_then(|balance| balance.parse().ok()).unwrap_or(default.starting_balance);
+        let admin_username = std::env::var("ADMIN_USERNAME").ok();
+        let admin_password = std::env::var("ADMIN_PASSWORD").ok();
+        WorkerConfig { starting_balance, admin_username, admin_password, ..default }
+    }
+}
 
 pub fn spawn_background_worker() -> mpsc::Sender<(Request)> {
+    spawn_worker(WorkerConfig::from_env())
+}
+
+pub fn spawn_worker(config : WorkerConfig) -> mpsc::Sender<(Request)> {
     let (tx, mut rx) = mpsc::channel::<(Request)>(30);
+    let WorkerConfig { starting_balance, mut funding, solvency_check_interval, admin_username, admin_password } = config;
     tokio::spawn(async move {
         let mut users: HashMap<String, User> = HashMap::new(); //  Hashmap of all users
+        // the admin can not sign up, its account exists from the start and holds no money
+        if let (Some(username), Some(password)) = (&admin_username, &admin_password) {
+            users.insert(username.clone(), User {
+                username: username.clone(),
+                password: hash_password(password),
+                balance: 0,
+                reserved_balance: 0,
+                pending_withdrawal: 0,
+                holdings: HashMap::new(),
+                reservations: HashMap::new(),
+                positions: HashMap::new(),
+            });
+        }
         let mut markets: HashMap<String, Market> = HashMap::new();
+        let mut ledger = Ledger::new();     // journal of every cash and share movement
+        let mut withdrawals: HashMap<String, Withdrawal> = HashMap::new();    // withdrawal_id → withdrawal
+        let mut handled : u64 = 0;
         loop {
-            match rx.recv().await {
-                Some(req) => {
-                    match req {
-                        Request::Signup {
-                            username,
-                            password,
-                            resp,
-                        } => {
-                            match users.get(&username) {
-                                Some(user) => {
-                                    let _ = resp.send(Err(
-                                        "Username already exists , use a different username "
-                                            .to_string(),
-                                    ));
-                                }
-                                None => {
-                                    // balance on signup is given = 5000
-                                    users.insert(
-                                        username.clone(),
-                                        User {
-                                            username: username.clone(),
-                                            password,
-                                            balance: 5000,
-                                            holdings: HashMap::new(),
-                                        },
-                                    );
-                                    let _ = resp.send(Ok(username));
-                                }
+            // debug builds check that the last request left every balance accounted for; this runs
+            // before waiting for the next request, as the arms below end early with `continue`
+            if cfg!(debug_assertions) && handled.is_multiple_of(solvency_check_interval.max(1)) {
+                let report = solvency_report(&users, &markets, &ledger);
+                assert!(report.solvent, "solvency violated after {} requests: {:#?}", handled, report);
+            }
+            let Some(req) = rx.recv().await else {
+                break;
+            };
+            handled += 1;
+            // good-till-date orders are taken off the books before anything can observe or match them
+            let now = Utc::now();
+            for market in markets.values_mut() {
+                market.expire_orders(now, &mut users, &mut ledger);
+            }
+            match req {
+                Request::Signup {
+                    username,
+                    password,
+                    resp,
+                } => {
+                    // trades against the market maker name it as the counterparty, and whoever holds
+                    // the admin name can decide withdrawals
+                    if username == AMM_USERNAME || admin_username.as_ref() == Some(&username) {
+                        let _ = resp.send(Err(ExchangeError::UsernameReserved));
+                        continue;
+                    }
+                    match users.get(&username) {
+                        Some(user) => {
+                            let _ = resp.send(Err(ExchangeError::UsernameTaken));
+                        }
+                        None => {
+                            // every user starts with the configured balance
+                            users.insert(
+                                username.clone(),
+                                User {
+                                    username: username.clone(),
+                                    password,
+                                    balance: starting_balance,
+                                    reserved_balance: 0,
+                                    pending_withdrawal: 0,
+                                    holdings: HashMap::new(),
+                                    reservations: HashMap::new(),
+                                    positions: HashMap::new(),
+                                },
+                            );
+                            ledger.post(Account::Available(username.clone()), Account::Funding, Asset::Cash, starting_balance, Reason::SignupCredit, Reference::default());
+                            let _ = resp.send(Ok(username));
+                        }
+                    }
+                }
+                Request::Signin {
+                    username,
+                    password,
+                    resp,
+                } => {
+                    match users.get(&username) {
+                        Some(user) => {
+                            if verify_password(&password, &user.password) {
+                                println!("User signed in: {}", username);
+                                // Send Ok with the username
+                                let _ = resp.send(Ok(username));
+                            } else {
+                                let _ = resp.send(Err(ExchangeError::InvalidCredentials));
                             }
                         }
-                        Request::Signin {
-                            username,
-                            password,
-                            resp,
-                        } => {
-                            match users.get(&username) {
-                                Some(user) => {
-                                    if verify_password(&password, &user.password) {
-                                        println!("User signed in: {}", username);
-                                        // Send Ok with the username
-                                        let _ = resp.send(Ok(username));
-                                    } else {
-                                        let _ = resp.send(Err("Invalid password".to_string()));
-                                    }
+                        None => {
+                            // User not found
+                            let _ = resp.send(Err(ExchangeError::UserNotFound));
+                        }
+                    }
+                }
+                Request::CreateLimitOrder {
+                    username,
+                    stock_type,
+                    price,
+                    resp,
+                    quantity,
+                    ordertype,
+                    market_id,
+                    time_in_force,
+                    post_only,
+                    self_trade_prevention,
+                    display_quantity,
+                } => {
+                    if let Some(user) = users.get_mut(&username) {
+                        if let Some(market) = markets.get_mut(&market_id) {
+                            // check if market is not ended
+                            if market.is_settled {
+                                let _ = resp.send(Err(ExchangeError::MarketSettled));
+                                continue;
+                            }
+                            let order_id = nanoid!();
+                            let mut order = Order {
+                                order_id: order_id.clone(),
+                                price,
+                                quantity,
+                                stock_type,
+                                username: username.clone(),
+                                timestamp: Utc::now(),
+                                ordertype: ordertype.clone(),
+                                market_id: market_id.clone(),
+                                time_in_force,
+                                post_only,
+                                self_trade_prevention,
+                                display_quantity,
+                                hidden_quantity: 0,
+                                original_quantity: quantity,
+                            };
+                            match market.add_limit_order(order, user, &mut ledger) {
+                                Ok(fill) => {
+                                    //  balance update of both the parties done here
+                                    settle_trades(&mut users, &mut ledger, &market_id, Some(price), &fill.trades);
+                                    fire_stop_orders(market, &mut users, &mut ledger);
+                                    let _ = resp.send(Ok(OrderResult::new(order_id, &ordertype, quantity, Some(price), fill)));
                                 }
-                                None => {
-                                    // User not found
-                                    let _ = resp.send(Err("User not found".to_string()));
+                                Err(err) => {
+                                    let _ = resp.send(Err(err));
                                 }
                             }
+                        } else {
+                            let _ = resp.send(Err(ExchangeError::MarketNotFound));
                         }
-                        Request::CreateLimitOrder {
-                            username,
-                            stock_type,
-                            price,
-                            resp,
-                            quantity,
-                            ordertype,
-                            market_id,
-                        } => {
-                            if let Some(user) = users.get_mut(&username) {
-                                if let Some(market) = markets.get_mut(&market_id) {
-                                    // check if market is not ended
-                                    if market.is_settled {
-                                        let _ = resp.send(Err(
-                                            "Market is already settled. No new orders allowed."
-                                                .to_string(),
-                                        ));
-                                        continue;
-                                    }
-                                    let mut order = Order {
-                                        price,
-                                        quantity,
-                                        stock_type,
-                                        username: username.clone(),
-                                        timestamp: Utc::now(),
-                                        ordertype,
-                                        market_id: market_id.clone(),
-                                    };
-                                    let mut trades = market.add_limit_order(order, user);
-                                    match trades {
-                                        Ok(mut trades) => {
-                                            for trade in trades.iter_mut() {
-                                                let seller_name = &trade.from;
-                                                let buyer_name = &trade.to;
-                                                if let [Some(buyer), Some(seller)] = users
-                                                    .get_disjoint_mut([buyer_name, seller_name])
-                                                {
-                                                    seller.balance +=
-                                                        trade.trade_price * trade.trade_qty; //seller balance update after trade executed
-
-                                                    let buyer_holdings = buyer
-                                                        .holdings
-                                                        .entry(market_id.clone())
-                                                        .or_default();
+                    } else {
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                        continue;
+                    }
+                }
 
-                                                    match trade.stock_type {
-                                                        StockType::StockA => {
-                                                            buyer_holdings.stock_a +=
-                                                                trade.trade_qty; //buyer's stock holdings update after trade executed
-                                                        }
-                                                        StockType::StockB => {
-                                                            buyer_holdings.stock_b +=
-                                                                trade.trade_qty; //buyer's stock update after trade executed
-                                                        }
-                                                    }
-                                                    if username == trade.to {
-                                                        let price_improvement =
-                                                            price - trade.trade_price; // if user got stocks at better price than asked
-                                                        if price_improvement > 0 {
-                                                            let refund =
-                                                                price_improvement * trade.trade_qty; // return the amount for that many stocks
-                                                            buyer.balance += refund;
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                            let msg = if trades.is_empty() {
-                                                "Order placed, waiting to be matched.".to_string()
-                                            } else {
-                                                format!("{:?}", trades)
-                                            };
-                                            let _ = resp.send(Ok(msg));
-                                        }
-                                        Err(err) => {
-                                            let _ = resp.send(Err(err));
-                                        }
-                                    }
-                                    //  balance update of both the parties done here
-                                } else {
-                                    let _ = resp.send(Err("Invalid option".to_string()));
-                                }
-                            } else {
-                                let _ = resp.send(Err("User not found".to_string()));
+                Request::CreateMarketOrder {
+                    username,
+                    stock_type,
+                    quantity,
+                    ordertype,
+                    resp,
+                    market_id,
+                    self_trade_prevention,
+                    limits,
+                    all_or_nothing,
+                } => {
+                    if let Some(user) = users.get_mut(&username) {
+                        if let Some(market) = markets.get_mut(&market_id) {
+                            // checking if market has not ended
+                            if market.is_settled {
+                                let _ = resp.send(Err(ExchangeError::MarketSettled));
                                 continue;
                             }
+                            let order_id = nanoid!();
+                            let trades = market.execute_market_order(
+                                order_id.clone(),
+                                username.clone(),
+                                ordertype.clone(),
+                                quantity,
+                                stock_type,
+                                user,
+                                &mut ledger,
+                                market_id.clone(),
+                                self_trade_prevention,
+                                limits,
+                                all_or_nothing
+                            );
+                            match trades {
+                                Ok(fill) => {
+                                    // the market order already paid or handed over its stock
+                                    settle_trades(&mut users, &mut ledger, &market_id, None, &fill.trades);
+                                    fire_stop_orders(market, &mut users, &mut ledger);
+                                    let _ = resp.send(Ok(OrderResult::new(order_id, &ordertype, quantity, None, fill)));
+                                }
+                                Err(err) => {
+                                    let _ = resp.send(Err(err));
+                                }
+                            }
+                        } else {
+                            let _ = resp.send(Err(ExchangeError::MarketNotFound));
                         }
-
-                        Request::CreateMarketOrder {
-                            username,
-                            stock_type,
+                    } else {
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                        continue;
+                    }
+                }
+                Request::CreateStopOrder {
+                    username,
+                    stock_type,
+                    quantity,
+                    ordertype,
+                    market_id,
+                    trigger_price,
+                    limit_price,
+                    self_trade_prevention,
+                    resp,
+                } => {
+                    let Some(user) = users.get_mut(&username) else {
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                        continue;
+                    };
+                    let Some(market) = markets.get_mut(&market_id) else {
+                        let _ = resp.send(Err(ExchangeError::MarketNotFound));
+                        continue;
+                    };
+                    if market.is_settled {
+                        let _ = resp.send(Err(ExchangeError::MarketSettled));
+                        continue;
+                    }
+                    let order_id = nanoid!();
+                    let stop = StopOrder {
+                        order : Order {
+                            order_id: order_id.clone(),
+                            price: limit_price.unwrap_or_default(),     // set by the market for plain stops
                             quantity,
+                            stock_type,
+                            username: username.clone(),
+                            timestamp: Utc::now(),
                             ordertype,
-                            resp,
-                            market_id,
-                        } => {
-                            if let Some(user) = users.get_mut(&username) {
-                                if let Some(market) = markets.get_mut(&market_id) {
-                                    // checking if market has not ended
-                                    if market.is_settled {
-                                        let _ = resp.send(Err(
-                                            "Market is already settled. No new orders allowed."
-                                                .to_string(),
-                                        ));
-                                        continue;
-                                    }
-                                    let trades = market.execute_market_order(
-                                        username.clone(),
-                                        ordertype,
-                                        quantity,
-                                        stock_type,
-                                        user,
-                                        market_id.clone()
-                                    );
-                                    match trades {
-                                        Ok(trades) => {
-                                            for trade in trades.iter() {
-                                                let buyer_name = &trade.to;
-                                                let seller_name = &trade.from;
-                                                // handling self-trade
-                                                if seller_name == buyer_name {
-                                                    continue;
-                                                }
-                                                if let [Some(buyer), Some(seller)] = users
-                                                    .get_disjoint_mut([buyer_name, seller_name])
-                                                {                               
-                                                    seller.balance += trade.trade_price * trade.trade_qty;
-                                                    let buyer_holdings = buyer
-                                                        .holdings
-                                                        .entry(market_id.clone())
-                                                        .or_default();
-                                            
-                                                    match trade.stock_type {
-                                                        StockType::StockA => {
-                                                            buyer_holdings.stock_a += trade.trade_qty;
-                                                            
-                                                        }
-                                                        StockType::StockB => {
-                                                            buyer_holdings.stock_b += trade.trade_qty;
-                                                            
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                            let msg = if trades.is_empty() {
-                                                "Order placed, waiting to be matched.".to_string()
-                                            } else {
-                                                format!("{:?}", trades)
-                                            };
-                                            let _ = resp.send(Ok(msg));
-                                        }
-                                        Err(err) => {
-                                            let _ = resp.send(Err(err));
-                                        }
-                                    }
-                                } else {
-                                    let _ = resp.send(Err("Invalid option".to_string()));
-                                }
-                            } else {
-                                let _ = resp.send(Err("User not found".to_string()));
-                                continue;
-                            }
+                            market_id: market_id.clone(),
+                            time_in_force: TimeInForce::GoodTillCancel,
+                            post_only: PostOnly::Disabled,
+                            self_trade_prevention,
+                            display_quantity: None,
+                            hidden_quantity: 0,
+                            original_quantity: quantity,
+                        },
+                        trigger_price,
+                        is_market : limit_price.is_none()
+                    };
+                    match market.add_stop_order(stop, user, &mut ledger) {
+                        Ok(()) => {
+                            let msg = format!("Stop order placed, triggers at {}.", trigger_price);
+                            let _ = resp.send(Ok(LimitOrderResponse { order_id, msg }));
+                        }
+                        Err(err) => {
+                            let _ = resp.send(Err(err));
+                        }
+                    }
+                }
+                Request::QuoteMarketOrder {
+                    username,
+                    stock_type,
+                    quantity,
+                    ordertype,
+                    market_id,
+                    self_trade_prevention,
+                    limits,
+                    resp,
+                } => {
+                    let Some(user) = users.get(&username) else {
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                        continue;
+                    };
+                    let Some(market) = markets.get(&market_id) else {
+                        let _ = resp.send(Err(ExchangeError::MarketNotFound));
+                        continue;
+                    };
+                    if market.is_settled {
+                        let _ = resp.send(Err(ExchangeError::MarketSettled));
+                        continue;
+                    }
+                    let _ = resp.send(market.quote_market_order(&username, ordertype, quantity, stock_type, user, self_trade_prevention, limits));
+                }
+                Request::TradeAmm {
+                    username,
+                    stock_type,
+                    quantity,
+                    ordertype,
+                    market_id,
+                    limit,
+                    resp,
+                } => {
+                    let Some(user) = users.get_mut(&username) else {
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                        continue;
+                    };
+                    let Some(market) = markets.get_mut(&market_id) else {
+                        let _ = resp.send(Err(ExchangeError::MarketNotFound));
+                        continue;
+                    };
+                    if market.is_settled {
+                        let _ = resp.send(Err(ExchangeError::MarketSettled));
+                        continue;
+                    }
+                    match market.trade_amm(user, &mut ledger, stock_type, ordertype, quantity, limit) {
+                        Ok((amount, trade)) => {
+                            fire_stop_orders(market, &mut users, &mut ledger);
+                            let _ = resp.send(Ok(AmmTradeResult { amount, trade }));
+                        }
+                        Err(err) => {
+                            let _ = resp.send(Err(err));
+                        }
+                    }
+                }
+                Request::CreateMarket {
+                    username,
+                    market_name,
+                    tick_size,
+                    outcomes,
+                    liquidity,
+                    resp,
+                } => {
+                    let Some(user) = users.get_mut(&username) else {
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                        continue;
+                    };
+                    // every valid price has to be reachable in whole ticks
+                    let tick_size = tick_size.unwrap_or(1);
+                    if tick_size == 0 || !PAIR_PAYOUT.is_multiple_of(tick_size) {
+                        let _ = resp.send(Err(ExchangeError::Validation(format!("Tick size must divide the pair payout of {}", PAIR_PAYOUT))));
+                        continue;
+                    }
+                    let outcomes = outcomes.unwrap_or_else(|| vec!["StockA".to_string(), "StockB".to_string()]);
+                    if outcomes.len() < 2 {
+                        let _ = resp.send(Err(ExchangeError::Validation("A market needs at least two outcomes".to_string())));
+                        continue;
+                    }
+                    // the creator pays for the market maker's worst case loss up front
+                    let amm = match liquidity {
+                        Some(0) => {
+                            let _ = resp.send(Err(ExchangeError::Validation("Liquidity must be greater than zero".to_string())));
+                            continue;
                         }
-                        Request::CreateMarket {
-                            username,
-                            market_name,
-                            resp,
-                        } => {
-                            if !users.contains_key(&username) {
-                                let _ = resp.send(Err("User does not exist".to_string()));
+                        Some(liquidity) => {
+                            let subsidy = Amm::subsidy(liquidity, outcomes.len());
+                            if user.balance < subsidy {
+                                let _ = resp.send(Err(ExchangeError::InsufficientFunds { required : subsidy, available : user.balance }));
                                 continue;
                             }
-                            let market = Market::initialise_market(market_name, username);
-                            let market_id = market.market_id.clone();
-                            match  markets.insert(market.market_id.to_string(), market) {
-                                Some(market) => {
-                                    let _ = resp.send(Err("Market already exists".to_string()));
-                                }
-                                None => {
-                                    let _ = resp.send(Ok(market_id));   // hashmap insert returns none when added and Some when already exists
-                                }
-                            };
-                            
-                        }
-                        Request::MergeStocks { 
-                            username, 
-                            market_id, 
-                            amount, 
-                            resp
-                         } => {
-                            if let Some(user) = users.get_mut(&username) {
-                                // check holdings of both stock
-                                let holdings = user.holdings.entry(market_id).or_default();
-                                if holdings.stock_a < amount || holdings.stock_b < amount {
-                                    let _ = resp.send(Err("Insufficient token pairs to redeem".to_string()));
-                                    continue;
-                                }
+                            user.balance -= subsidy;
+                            Some(Amm::new(liquidity, outcomes.len()))
+                        }
+                        None => None,
+                    };
+                    let mut market = Market::initialise_market(market_name, username.clone(), outcomes, tick_size);
+                    let subsidy = amm.as_ref().map_or(0, |amm| amm.cash);
+                    market.amm = amm;
+                    let market_id = market.market_id.clone();
+                    ledger.post(Account::MarketMaker(market_id.clone()), Account::Available(username), Asset::Cash, subsidy, Reason::MarketSubsidy, Reference::market(&market_id));
+                    match  markets.insert(market.market_id.to_string(), market) {
+                        Some(market) => {
+                            let _ = resp.send(Err(ExchangeError::MarketAlreadyExists));
+                        }
+                        None => {
+                            let _ = resp.send(Ok(market_id));   // hashmap insert returns none when added and Some when already exists
+                        }
+                    };
+                    
+                }
+                Request::MergeStocks { 
+                    username, 
+                    market_id, 
+                    amount, 
+                    resp
+                 } => {
+                    if let Some(user) = users.get_mut(&username) {
+                        let Some(market) = markets.get_mut(&market_id) else {
+                            let _ = resp.send(Err(ExchangeError::MarketNotFound));
+                            continue;
+                        };
+                        if market.is_settled {
+                            let _ = resp.send(Err(ExchangeError::MarketSettled));
+                            continue;
+                        }
+                        // a full set needs a share of every outcome
+                        let outcomes = (0..market.outcomes.len()).map(StockType);
+                        let holdings = user.holdings.entry(market_id.clone()).or_default();
+                        let available = outcomes.clone().map(|stock_type| holdings.stock(&stock_type)).min().unwrap_or(0);
+                        if available < amount {
+                            let _ = resp.send(Err(ExchangeError::InsufficientStock { required : amount, available }));
+                            continue;
+                        }
+                        let Some(payout) = amount.checked_mul(PAIR_PAYOUT) else {
+                            let _ = resp.send(Err(ExchangeError::Validation(format!("Amount of {} sets overflows their payout of {} each", amount, PAIR_PAYOUT))));
+                            continue;
+                        };
 
-                                holdings.stock_a -= amount;
-                                holdings.stock_b -= amount;
-                                user.balance += amount;
-                                let _ = resp.send(Ok(format!("Redeemed {} pairs for ${}", amount, amount)));
-                            }
-                         }
-                         Request::SplitStocks { 
-                            username, 
-                            market_id, 
-                            amount, 
-                            resp
-                         } => {
-                            if let Some(user) = users.get_mut(&username){
-                                // check collateral and then lock
-                                if user.balance < amount {
-                                    let _ = resp.send(Err("Insufficient funds to mint".to_string()));
-                                    continue;
-                                }
-                                // checking if market exists
-                                let market = markets.get(&market_id) ;
-                                if market.is_none() {
-                                    let _ = resp.send(Err("Market does not exists".to_string()));
-                                    continue;
+                        for stock_type in outcomes.clone() {
+                            *holdings.stock_mut(&stock_type) -= amount;
+                            ledger.post(Account::Issuance(market_id.clone()), Account::Available(username.clone()), Asset::shares(&market_id, &stock_type), amount, Reason::Merge, Reference::market(&market_id));
+                        }
+                        // the payout is realised against the cost of each outcome's shares
+                        for (stock_type, proceeds) in outcomes.zip(split_evenly(payout, market.outcomes.len())) {
+                            user.position_mut(&market_id, &stock_type).sell(amount, proceeds);
+                        }
+                        market.collateral -= payout;
+                        user.balance += payout;
+                        ledger.post(Account::Available(username.clone()), Account::Collateral(market_id.clone()), Asset::Cash, payout, Reason::Merge, Reference::market(&market_id));
+                        let _ = resp.send(Ok(format!("Redeemed {} pairs for ${}", amount, payout)));
+                    }
+                    else{
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                    }
+                 }
+                 Request::SplitStocks { 
+                    username, 
+                    market_id, 
+                    amount, 
+                    resp
+                 } => {
+                    if let Some(user) = users.get_mut(&username){
+                        // check collateral and then lock, every pair is backed by PAIR_PAYOUT
+                        let Some(cost) = amount.checked_mul(PAIR_PAYOUT) else {
+                            let _ = resp.send(Err(ExchangeError::Validation(format!("Amount of {} sets overflows their payout of {} each", amount, PAIR_PAYOUT))));
+                            continue;
+                        };
+                        if user.balance < cost {
+                            let _ = resp.send(Err(ExchangeError::InsufficientFunds { required : cost, available : user.balance }));
+                            continue;
+                        }
+                        // checking if market exists
+                        let Some(market) = markets.get_mut(&market_id) else {
+                            let _ = resp.send(Err(ExchangeError::MarketNotFound));
+                            continue;
+                        };
+                        if market.is_settled {
+                            let _ = resp.send(Err(ExchangeError::MarketSettled));
+                            continue;
+                        }
+                        user.balance -= cost; // lock collateral
+                        market.collateral += cost;
+                        ledger.post(Account::Collateral(market_id.clone()), Account::Available(username.clone()), Asset::Cash, cost, Reason::Split, Reference::market(&market_id));
+                        // mint an equal amount of every outcome to user
+                        let holdings = user.holdings.entry(market_id.clone()).or_default();
+                        for index in 0..market.outcomes.len() {
+                            *holdings.stock_mut(&StockType(index)) += amount;
+                            ledger.post(Account::Available(username.clone()), Account::Issuance(market_id.clone()), Asset::shares(&market_id, &StockType(index)), amount, Reason::Split, Reference::market(&market_id));
+                        }
+                        // each outcome's shares cost an even part of the sets
+                        for (index, part) in split_evenly(cost, market.outcomes.len()).into_iter().enumerate() {
+                            user.position_mut(&market_id, &StockType(index)).buy(amount, part);
+                        }
+                        let _ = resp.send(Ok(format!("Minted {} of every outcome", amount)));
+                    }
+                    else{
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                    }
+                 }
+                 Request::UserDetails { 
+                    username, 
+                    resp 
+                } => {
+                     match users.get(&username) { 
+                        Some(user) => {
+                            let user_details = UserDetails{
+                                balance : user.balance,
+                                reserved_balance : user.reserved_balance,
+                                pending_withdrawal : user.pending_withdrawal,
+                                holdings : user.holdings.clone(),
+                                reservations : user.reservations.clone()
+                            };
+                            let _ = resp.send(Ok(user_details));
+                        }None => {
+                            let _ = resp.send(Err(ExchangeError::UserNotFound));
+                            continue;
+                        }
+                     };
+                }
+                Request::GetOrderbook { market_id
+                    , resp
+                 } => {
+                    let market = markets.get(&market_id) ;
+                    if market.is_none() {
+                        let _ = resp.send(Err(ExchangeError::MarketNotFound));
+                        continue;
+                    }
+                    let market = market.unwrap();
+                    let _ = resp.send(Ok(Orderbooks {
+                        outcomes : market.outcomes.clone(),
+                        books : market.books.iter().map(|book| book.public_view()).collect()
+                    }));
+                 }
+                Request::GetOpenOrders {
+                    username,
+                    resp
+                } => {
+                    if !users.contains_key(&username) {
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                        continue;
+                    }
+                    let mut orders : Vec<OpenOrder> = markets.values().flat_map(|market| market.open_orders(&username)).collect();
+                    orders.sort_by_key(|order| order.timestamp);
+                    let _ = resp.send(Ok(orders));
+                }
+                Request::GetLedger {
+                    username,
+                    resp
+                } => {
+                    if !users.contains_key(&username) {
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                        continue;
+                    }
+                    let _ = resp.send(Ok(ledger.entries_of(&username)));
+                }
+                Request::CheckSolvency { resp } => {
+                    let _ = resp.send(Ok(solvency_report(&users, &markets, &ledger)));
+                }
+                Request::Deposit {
+                    username,
+                    amount,
+                    resp
+                } => {
+                    let Some(user) = users.get_mut(&username) else {
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                        continue;
+                    };
+                    if amount == 0 {
+                        let _ = resp.send(Err(ExchangeError::Validation("Amount must be greater than zero".to_string())));
+                        continue;
+                    }
+                    let Some(funding) = funding.as_mut() else {
+                        let _ = resp.send(Err(ExchangeError::FundingUnavailable));
+                        continue;
+                    };
+                    // checked before the money is pulled in, so a deposit that can not be credited never arrives
+                    let Some(balance) = user.balance.checked_add(amount) else {
+                        let _ = resp.send(Err(ExchangeError::Validation(format!("Deposit of {} overflows the balance of {}", amount, user.balance))));
+                        continue;
+                    };
+                    // the money has to arrive before it is credited
+                    match funding.deposit(&username, amount) {
+                        Ok(reference) => {
+                            user.balance = balance;
+                            ledger.post(Account::Available(username.clone()), Account::Funding, Asset::Cash, amount, Reason::Deposit, Reference::funding(&reference));
+                            let _ = resp.send(Ok(Deposit { reference, amount, balance : user.balance }));
+                        }
+                        Err(err) => {
+                            let _ = resp.send(Err(err));
+                        }
+                    }
+                }
+                Request::Withdraw {
+                    username,
+                    amount,
+                    resp
+                } => {
+                    let Some(user) = users.get_mut(&username) else {
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                        continue;
+                    };
+                    if amount == 0 {
+                        let _ = resp.send(Err(ExchangeError::Validation("Amount must be greater than zero".to_string())));
+                        continue;
+                    }
+                    // nothing could ever pay it out
+                    if funding.is_none() {
+                        let _ = resp.send(Err(ExchangeError::FundingUnavailable));
+                        continue;
+                    }
+                    if user.balance < amount {
+                        let _ = resp.send(Err(ExchangeError::InsufficientFunds { required : amount, available : user.balance }));
+                        continue;
+                    }
+                    // held until an admin approves or rejects it
+                    user.balance -= amount;
+                    user.pending_withdrawal += amount;
+                    let withdrawal = Withdrawal {
+                        withdrawal_id : nanoid!(),
+                        username : username.clone(),
+                        amount,
+                        status : WithdrawalStatus::Pending,
+                        requested_at : Utc::now(),
+                        decided_at : None,
+                        reference : None
+                    };
+                    ledger.post(Account::PendingWithdrawal(username.clone()), Account::Available(username), Asset::Cash, amount, Reason::WithdrawalRequested, Reference::funding(&withdrawal.withdrawal_id));
+                    withdrawals.insert(withdrawal.withdrawal_id.clone(), withdrawal.clone());
+                    let _ = resp.send(Ok(withdrawal));
+                }
+                Request::GetWithdrawals {
+                    username,
+                    resp
+                } => {
+                    if let Some(username) = &username && !users.contains_key(username) {
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                        continue;
+                    }
+                    let mut listed : Vec<Withdrawal> = withdrawals.values().filter(|withdrawal| match &username {
+                        Some(username) => withdrawal.username == *username,
+                        None => withdrawal.status == WithdrawalStatus::Pending,
+                    }).cloned().collect();
+                    listed.sort_by_key(|withdrawal| withdrawal.requested_at);
+                    let _ = resp.send(Ok(listed));
+                }
+                Request::DecideWithdrawal {
+                    withdrawal_id,
+                    decision,
+                    resp
+                } => {
+                    let Some(withdrawal) = withdrawals.get_mut(&withdrawal_id) else {
+                        let _ = resp.send(Err(ExchangeError::WithdrawalNotFound));
+                        continue;
+                    };
+                    if withdrawal.status != WithdrawalStatus::Pending {
+                        let _ = resp.send(Err(ExchangeError::WithdrawalNotPending));
+                        continue;
+                    }
+                    let Some(user) = users.get_mut(&withdrawal.username) else {
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                        continue;
+                    };
+                    let pending = Account::PendingWithdrawal(user.username.clone());
+                    match decision {
+                        WithdrawalDecision::Approve => {
+                            // a failed payout stays pending and can be approved again
+                            let payout = funding.as_mut().ok_or(ExchangeError::FundingUnavailable)
+                                .and_then(|funding| funding.withdraw(&user.username, withdrawal.amount));
+                            match payout {
+                                Ok(reference) => {
+                                    ledger.post(Account::Funding, pending, Asset::Cash, withdrawal.amount, Reason::WithdrawalApproved, Reference::funding(&withdrawal_id));
+                                    withdrawal.status = WithdrawalStatus::Approved;
+                                    withdrawal.reference = Some(reference);
                                 }
-                                user.balance -= amount; // lock collateral
-                                // mint equal amount a and b stocks to user
-                                let holdings = user.holdings.entry(market_id.clone()).or_insert(
-                                    UserHoldings { 
-                                        stock_a:0 , 
-                                        stock_b: 0
-                                    }
-                                );
-                                holdings.stock_a += amount;
-                                holdings.stock_b += amount;
-                                let _ = resp.send(Ok(format!("Minted {} of Stock A and B", amount)));
-                            }
-                            else{
-                                let _ = resp.send(Err("User not found".to_string()));
-                            }
-                         }
-                         Request::UserDetails { 
-                            username, 
-                            resp 
-                        } => {
-                             let user = match users.get(&username) { 
-                                Some(user) => {
-                                    let userDetails = UserDetails{
-                                        balance : user.balance,
-                                        holdings : user.holdings.clone()
-                                    };
-                                    let _ = resp.send(Ok(userDetails));
-                                }None => {
-                                    let _ = resp.send(Err("User does not exist".to_string()));
+                                Err(err) => {
+                                    let _ = resp.send(Err(err));
                                     continue;
                                 }
-                             };
-                        }
-                        Request::GetOrderbook { market_id
-                            , resp
-                         } => {
-                            let market = markets.get(&market_id) ;
-                            if market.is_none() {
-                                let _ = resp.send(Err("Market does not exists".to_string()));
-                                continue;
                             }
-                            let market = market.unwrap();
-                            let _ = resp.send(Ok(Orderbooks { stock_a: market.stock_a.clone(), stock_b: market.stock_b.clone()}));
-                         }
+                        }
+                        WithdrawalDecision::Reject => {
+                            user.balance += withdrawal.amount;
+                            ledger.post(Account::Available(user.username.clone()), pending, Asset::Cash, withdrawal.amount, Reason::WithdrawalRejected, Reference::funding(&withdrawal_id));
+                            withdrawal.status = WithdrawalStatus::Rejected;
+                        }
+                    }
+                    user.pending_withdrawal -= withdrawal.amount;
+                    withdrawal.decided_at = Some(Utc::now());
+                    let _ = resp.send(Ok(withdrawal.clone()));
+                }
+                Request::GetPortfolio {
+                    username,
+                    resp
+                } => {
+                    let Some(user) = users.get(&username) else {
+                        let _ = resp.send(Err(ExchangeError::UserNotFound));
+                        continue;
+                    };
+                    let mut positions : Vec<PortfolioPosition> = markets.values().flat_map(|market| market.positions_of(user)).collect();
+                    positions.sort_by(|a, b| (&a.market_id, a.stock_type.0).cmp(&(&b.market_id, b.stock_type.0)));
+                    let portfolio = Portfolio {
+                        realised_pnl : positions.iter().map(|position| position.realised_pnl).sum(),
+                        unrealised_pnl : positions.iter().filter_map(|position| position.unrealised_pnl).sum(),
+                        positions
+                    };
+                    let _ = resp.send(Ok(portfolio));
+                }
+                Request::GetDepth {
+                    market_id,
+                    levels,
+                    resp
+                } => {
+                    let Some(market) = markets.get(&market_id) else {
+                        let _ = resp.send(Err(ExchangeError::MarketNotFound));
+                        continue;
+                    };
+                    let _ = resp.send(Ok(MarketDepth {
+                        outcomes : market.outcomes.clone(),
+                        books : market.books.iter().map(|book| book.depth(levels)).collect()
+                    }));
+                }
+                Request::GetTicker {
+                    market_id,
+                    resp
+                } => {
+                    let Some(market) = markets.get(&market_id) else {
+                        let _ = resp.send(Err(ExchangeError::MarketNotFound));
+                        continue;
+                    };
+                    let _ = resp.send(Ok(Ticker {
+                        outcomes : market.outcomes.clone(),
+                        tickers : (0..market.outcomes.len()).map(|index| market.ticker(&StockType(index))).collect()
+                    }));
+                }
+                Request::AmendOrder {
+                    username,
+                    order_id,
+                    price,
+                    quantity,
+                    resp
+                } => {
+                    let user = match users.get_mut(&username) {
+                        Some(user) => user,
+                        None => {
+                            let _ = resp.send(Err(ExchangeError::UserNotFound));
+                            continue;
+                        }
+                    };
+                    let market = match markets.values_mut().find(|market| market.get_order(&order_id, &username).is_some()) {
+                        Some(market) => market,
+                        None => {
+                            let _ = resp.send(Err(ExchangeError::OrderNotFound));
+                            continue;
+                        }
+                    };
+                    let market_id = market.market_id.clone();
+                    match market.amend_order(&order_id, price, quantity, user, &mut ledger) {
+                        Ok((limit_price, result)) => {
+                            settle_trades(&mut users, &mut ledger, &market_id, Some(limit_price), &result.trades);
+                            fire_stop_orders(market, &mut users, &mut ledger);
+                            let _ = resp.send(Ok(result));
+                        }
+                        Err(err) => {
+                            let _ = resp.send(Err(err));
+                        }
+                    }
+                }
+                Request::CancelOrder {
+                    username,
+                    order_id,
+                    resp
+                } => {
+                    let user = match users.get_mut(&username) {
+                        Some(user) => user,
+                        None => {
+                            let _ = resp.send(Err(ExchangeError::UserNotFound));
+                            continue;
+                        }
+                    };
+                    // order ids are unique across markets, so the first book holding it is the one
+                    let cancelled = markets.values_mut().find_map(|market| market.cancel_order(&order_id, user, &mut ledger));
+                    match cancelled {
+                        Some(order) => {
+                            let _ = resp.send(Ok(format!("Order {} cancelled, {} unfilled released", order.order_id, order.total_quantity())));
+                        }
+                        None => {
+                            let _ = resp.send(Err(ExchangeError::OrderNotFound));
+                        }
+                    }
+                }
+                Request::ResolveMarket {
+                    username,
+                    market_id,
+                    winning_outcome,
+                    resp
+                } => {
+                    let market = match markets.get_mut(&market_id) {
+                        Some(market) => market,
+                        None => {
+                            let _ = resp.send(Err(ExchangeError::MarketNotFound));
+                            continue;
+                        }
+                    };
+                    // only the creator of the market can decide its outcome
+                    if market.created_by != username {
+                        let _ = resp.send(Err(ExchangeError::NotMarketCreator));
+                        continue;
                     }
+                    if market.is_settled {
+                        let _ = resp.send(Err(ExchangeError::MarketSettled));
+                        continue;
+                    }
+                    if let WinningOutcome::Outcome(index) = winning_outcome && index >= market.outcomes.len() {
+                        let _ = resp.send(Err(ExchangeError::UnknownOutcome { outcome : index, outcomes : market.outcomes.len() }));
+                        continue;
+                    }
+                    let _ = resp.send(market.resolve(winning_outcome, &mut users, &mut ledger));
                 }
-                None => break,
             }
         }
     });
     tx
 }
+
+// Places every stop order whose trigger the last trades crossed. Their trades can trigger further
+// stops, so this runs until no trigger fires. A triggered order hands its reservation back and is then
+// placed like any new order, which locks or pays again.
+fn fire_stop_orders(market : &mut Market , users : &mut HashMap<String, User> , ledger : &mut Ledger) {
+    loop {
+        let triggered = market.take_triggered_stops();
+        if triggered.is_empty() {
+            break;
+        }
+        for stop in triggered {
+            let Some(user) = users.get_mut(&stop.order.username) else {
+                continue;
+            };
+            stop.order.release(user, ledger);
+            let order = stop.order;
+            let username = order.username.clone();
+            let market_id = market.market_id.clone();
+            let fill = if stop.is_market {
+                market.execute_market_order(order.order_id, username.clone(), order.ordertype, order.quantity, order.stock_type, user, ledger, market_id.clone(), order.self_trade_prevention, SlippageLimits::default(), false)
+                    .map(|fill| (fill, None))
+            } else {
+                let limit_price = order.price;
+                market.add_limit_order(order, user, ledger).map(|fill| (fill, Some(limit_price)))
+            };
+            // the reservation is already back with the user if the order can not be placed
+            if let Ok((fill, limit_price)) = fill {
+                settle_trades(users, ledger, &market_id, limit_price, &fill.trades);
+            }
+        }
+    }
+}
+
+// Pays out every trade and takes what each side gave up out of its order's reservation. The incoming
+// order of a buy locked its `limit_price` per share and gets back what it did not need.
+fn settle_trades(users : &mut HashMap<String, User> , ledger : &mut Ledger , market_id : &str , limit_price : Option<u64> , trades : &[Trade]) {
+    for trade in trades {
+        let seller_name = &trade.from;
+        let buyer_name = &trade.to;
+        // the aggressor is the side of the incoming (taker) order, the other one rested (maker)
+        let (buyer_order, seller_order) = match trade.aggressor {
+            Ordertype::Buy => (&trade.taker_order_id, &trade.maker_order_id),
+            Ordertype::Sell => (&trade.maker_order_id, &trade.taker_order_id),
+        };
+        let (buyer_order, seller_order) = (buyer_order.as_deref().unwrap_or_default(), seller_order.as_deref().unwrap_or_default());
+        if let [Some(buyer), Some(seller)] = users.get_disjoint_mut([buyer_name, seller_name]) {
+            let (value, counter_value) = (trade.value, PAIR_PAYOUT * trade.trade_qty - trade.value);
+            let shares = Asset::shares(market_id, &trade.stock_type);
+            // only binary markets mint and burn, so only they have a complement
+            let counter_shares = || Asset::shares(market_id, &trade.stock_type.complement());
+            let (buyer_account, seller_account) = (Account::Available(buyer_name.clone()), Account::Available(seller_name.clone()));
+            let (buyer_reserved, seller_reserved) = (Account::Reserved(buyer_name.clone()), Account::Reserved(seller_name.clone()));
+            let (collateral, issuance) = (Account::Collateral(market_id.to_string()), Account::Issuance(market_id.to_string()));
+            let reference = |order_id : &str| Reference::trade(market_id, Some(order_id), &trade.trade_id);
+            match trade.kind {
+                TradeKind::Transfer => {
+                    seller.consume(seller_order, 0, trade.trade_qty);
+                    seller.balance += value; //seller balance update after trade executed
+                    ledger.post(seller_account, buyer_reserved, Asset::Cash, value, Reason::Trade, reference(buyer_order));
+                    seller.position_mut(market_id, &trade.stock_type).sell(trade.trade_qty, value);
+
+                    buyer.consume(buyer_order, value, 0);
+                    let buyer_holdings = buyer.holdings.entry(market_id.to_string()).or_default();
+                    *buyer_holdings.stock_mut(&trade.stock_type) += trade.trade_qty; //buyer's stock holdings update after trade executed
+                    ledger.post(buyer_account, seller_reserved, shares, trade.trade_qty, Reason::Trade, reference(seller_order));
+                    buyer.position_mut(market_id, &trade.stock_type).buy(trade.trade_qty, value);
+                }
+                TradeKind::Mint => {
+                    // both sides paid into the new pairs, each gets its own outcome
+                    buyer.consume(buyer_order, value, 0);
+                    let buyer_holdings = buyer.holdings.entry(market_id.to_string()).or_default();
+                    *buyer_holdings.stock_mut(&trade.stock_type) += trade.trade_qty;
+                    ledger.post(collateral.clone(), buyer_reserved, Asset::Cash, value, Reason::Trade, reference(buyer_order));
+                    ledger.post(buyer_account, issuance.clone(), shares, trade.trade_qty, Reason::Trade, reference(buyer_order));
+                    buyer.position_mut(market_id, &trade.stock_type).buy(trade.trade_qty, value);
+                    seller.consume(seller_order, counter_value, 0);
+                    let counter_holdings = seller.holdings.entry(market_id.to_string()).or_default();
+                    *counter_holdings.stock_mut(&trade.stock_type.complement()) += trade.trade_qty;
+                    ledger.post(collateral, seller_reserved, Asset::Cash, counter_value, Reason::Trade, reference(seller_order));
+                    ledger.post(seller_account, issuance, counter_shares(), trade.trade_qty, Reason::Trade, reference(seller_order));
+                    seller.position_mut(market_id, &trade.stock_type.complement()).buy(trade.trade_qty, counter_value);
+                }
+                TradeKind::Burn => {
+                    // both sides gave up their shares, the released collateral is split by price
+                    seller.consume(seller_order, 0, trade.trade_qty);
+                    seller.balance += value;
+                    ledger.post(issuance.clone(), seller_reserved, shares, trade.trade_qty, Reason::Trade, reference(seller_order));
+                    ledger.post(seller_account, collateral.clone(), Asset::Cash, value, Reason::Trade, reference(seller_order));
+                    seller.position_mut(market_id, &trade.stock_type).sell(trade.trade_qty, value);
+                    buyer.consume(buyer_order, 0, trade.trade_qty);
+                    buyer.balance += counter_value;
+                    ledger.post(issuance, buyer_reserved, counter_shares(), trade.trade_qty, Reason::Trade, reference(buyer_order));
+                    ledger.post(buyer_account, collateral, Asset::Cash, counter_value, Reason::Trade, reference(buyer_order));
+                    buyer.position_mut(market_id, &trade.stock_type.complement()).sell(trade.trade_qty, counter_value);
+                }
+                TradeKind::Amm => {}    // the market already settled the user with the market maker
+            }
+
+            if let Some(limit_price) = limit_price && trade.aggressor == Ordertype::Buy {
+                // if user got stocks at better price than asked, return the difference for that many stocks
+                let refund = limit_price * trade.trade_qty - trade.value;
+                if refund > 0 {
+                    buyer.release(ledger, Reason::PriceImprovement, buyer_order, refund, 0);
+                }
+            }
+        }
+    }
+}
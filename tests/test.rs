@@ -1,13 +1,14 @@
 use exchange_rs::{
-    Orderbooks, Ordertype, StockType, UserDetails, models::request::Request, utils::hash_password, worker::processor::spawn_background_worker 
+    Account, AmmTradeResult, Asset, Deposit, DepthLevel, ExchangeError, JournalEntry, MockFunding, Reason, SolvencyReport, Withdrawal, WithdrawalDecision, WithdrawalStatus, LimitOrderResponse, OrderResult, OrderStatus, Portfolio, MarketDepth, OpenOrder, Orderbooks, Ordertype, PostOnly, Quote, QuoteLevel, SelfTradePrevention, SlippageLimits, StockType, Ticker, TimeInForce, TradeKind, UserDetails, WinningOutcome, models::request::Request, utils::hash_password, worker::processor::{WorkerConfig, spawn_background_worker, spawn_worker} 
 };
 use tokio::sync::{mpsc::Sender, oneshot};
+use actix_web::{ResponseError, body::to_bytes, http::StatusCode};
 
 async fn signup_user(
     tx: &Sender<Request>,
     user: &str,
     pass: &str,
-) -> Result<String, String> {
+) -> Result<String, ExchangeError> {
     let (resp_tx, resp_rx) = oneshot::channel();
     // Mimic the route: hash the password before sending
     let hashed_pass = hash_password(pass);
@@ -25,7 +26,7 @@ async fn signin_user(
     tx: &Sender<Request>,
     user: &str,
     pass: &str,
-) -> Result<String, String> {
+) -> Result<String, ExchangeError> {
     let (resp_tx, resp_rx) = oneshot::channel();
     
     let req = Request::Signin {
@@ -38,13 +39,23 @@ async fn signin_user(
     resp_rx.await.expect("Test worker response failed")
 }
 
+// optional market settings, unset fields fall back to the exchange defaults
+#[derive(Default)]
+struct MarketOptions<'a> {
+    tick_size : Option<u64>,
+    outcomes : Option<&'a [&'a str]>,
+    liquidity : Option<u64>     // seeds an AMM when set
+}
+
 async fn new_market(
     tx: &Sender<Request>,
     user: &str,
     market_name: &str,
-) -> Result<String , String> {
+    options: MarketOptions<'_>,
+) -> Result<String , ExchangeError> {
     let (resp_tx, resp_rx) = oneshot::channel();
-    let req = Request::CreateMarket { username: user.to_string(), market_name: market_name.to_string(), resp: resp_tx };
+    let outcomes = options.outcomes.map(|outcomes| outcomes.iter().map(|outcome| outcome.to_string()).collect());
+    let req = Request::CreateMarket { username: user.to_string(), market_name: market_name.to_string(), tick_size: options.tick_size, outcomes, liquidity: options.liquidity, resp: resp_tx };
     tx.send(req).await.expect("Test worker send failed");
     resp_rx.await.expect("Test worker response failed")
 }
@@ -54,17 +65,29 @@ async fn split_stocks (
     user: &str,
     market_id: &str,
     amount : u64
-) -> Result<String, String> {
+) -> Result<String, ExchangeError> {
     let (resp_tx, resp_rx) = oneshot::channel();
     let req = Request::SplitStocks { username: user.to_string(), market_id: market_id.to_string(), amount, resp: resp_tx };
     tx.send(req).await.expect("Test worker send failed");
     resp_rx.await.expect("Test worker response failed")
 }
 
+async fn merge_stocks (
+    tx: &Sender<Request>,
+    user: &str,
+    market_id: &str,
+    amount : u64
+) -> Result<String, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::MergeStocks { username: user.to_string(), market_id: market_id.to_string(), amount, resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
 async fn get_user_details (
     tx : &Sender<Request>,
     username :  &str,
-)-> Result<UserDetails, String>{
+)-> Result<UserDetails, ExchangeError>{
     let (resp_tx, resp_rx) = oneshot::channel();
     let req = Request::UserDetails { username: username.to_string(), resp: resp_tx };
     tx.send(req).await.expect("Test worker send failed");
@@ -79,7 +102,24 @@ async fn limit_order (
     quantity:u64,
     market_id : &str,
     ordertype: Ordertype, 
-)-> Result<String, String> {
+)-> Result<OrderResult, ExchangeError> {
+    limit_order_with(tx, username, stock_type, price, quantity, market_id, ordertype, TimeInForce::GoodTillCancel, PostOnly::Disabled, SelfTradePrevention::CancelNewest, None).await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn limit_order_with (
+    tx : &Sender<Request>,
+    username : &str, 
+    stock_type: StockType, 
+    price: u64, 
+    quantity:u64,
+    market_id : &str,
+    ordertype: Ordertype, 
+    time_in_force: TimeInForce,
+    post_only: PostOnly,
+    self_trade_prevention: SelfTradePrevention,
+    display_quantity: Option<u64>,
+)-> Result<OrderResult, ExchangeError> {
     let (resp_tx, resp_rx) = oneshot::channel();
     let req = Request::CreateLimitOrder { 
         username: username.to_string(), 
@@ -88,15 +128,226 @@ async fn limit_order (
         quantity, 
         ordertype, 
         market_id: market_id.to_string(), 
+        time_in_force,
+        post_only,
+        self_trade_prevention,
+        display_quantity,
+        resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+async fn market_order (
+    tx : &Sender<Request>,
+    username : &str, 
+    stock_type: StockType, 
+    quantity:u64,
+    market_id : &str,
+    ordertype: Ordertype, 
+    self_trade_prevention: SelfTradePrevention,
+)-> Result<OrderResult, ExchangeError> {
+    market_order_with(tx, username, stock_type, quantity, market_id, ordertype, self_trade_prevention, SlippageLimits::default(), false).await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn market_order_with (
+    tx : &Sender<Request>,
+    username : &str, 
+    stock_type: StockType, 
+    quantity:u64,
+    market_id : &str,
+    ordertype: Ordertype, 
+    self_trade_prevention: SelfTradePrevention,
+    limits: SlippageLimits,
+    all_or_nothing: bool,
+)-> Result<OrderResult, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::CreateMarketOrder { 
+        username: username.to_string(), 
+        stock_type, 
+        quantity, 
+        ordertype, 
+        market_id: market_id.to_string(), 
+        self_trade_prevention,
+        limits,
+        all_or_nothing,
+        resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn stop_order (
+    tx : &Sender<Request>,
+    username : &str,
+    stock_type: StockType,
+    quantity:u64,
+    market_id : &str,
+    ordertype: Ordertype,
+    trigger_price: u64,
+    limit_price: Option<u64>,
+)-> Result<LimitOrderResponse, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::CreateStopOrder {
+        username: username.to_string(),
+        stock_type,
+        quantity,
+        ordertype,
+        market_id: market_id.to_string(),
+        trigger_price,
+        limit_price,
+        self_trade_prevention: SelfTradePrevention::CancelNewest,
         resp: resp_tx };
     tx.send(req).await.expect("Test worker send failed");
     resp_rx.await.expect("Test worker response failed")
 }
 
+async fn quote (
+    tx : &Sender<Request>,
+    username : &str,
+    stock_type: StockType,
+    quantity:u64,
+    market_id : &str,
+    ordertype: Ordertype,
+    limits: SlippageLimits,
+)-> Result<Quote, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::QuoteMarketOrder {
+        username: username.to_string(),
+        stock_type,
+        quantity,
+        ordertype,
+        market_id: market_id.to_string(),
+        self_trade_prevention: SelfTradePrevention::CancelNewest,
+        limits,
+        resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn amm_trade (
+    tx : &Sender<Request>,
+    username : &str,
+    stock_type : StockType,
+    quantity : u64,
+    market_id : &str,
+    ordertype : Ordertype,
+    limit : Option<u64>,
+) -> Result<AmmTradeResult, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::TradeAmm { username: username.to_string(), stock_type, quantity, ordertype, market_id: market_id.to_string(), limit, resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+async fn get_open_orders (
+    tx : &Sender<Request>,
+    username : &str,
+)-> Result<Vec<OpenOrder>, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::GetOpenOrders { username: username.to_string(), resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+async fn get_ledger (
+    tx : &Sender<Request>,
+    username : &str,
+)-> Result<Vec<JournalEntry>, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::GetLedger { username: username.to_string(), resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+async fn check_solvency (
+    tx : &Sender<Request>,
+)-> Result<SolvencyReport, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::CheckSolvency { resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+async fn deposit (
+    tx : &Sender<Request>,
+    username : &str,
+    amount : u64,
+)-> Result<Deposit, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::Deposit { username: username.to_string(), amount, resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+async fn withdraw (
+    tx : &Sender<Request>,
+    username : &str,
+    amount : u64,
+)-> Result<Withdrawal, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::Withdraw { username: username.to_string(), amount, resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+async fn get_withdrawals (
+    tx : &Sender<Request>,
+    username : Option<&str>,
+)-> Result<Vec<Withdrawal>, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::GetWithdrawals { username: username.map(str::to_string), resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+async fn decide_withdrawal (
+    tx : &Sender<Request>,
+    withdrawal_id : &str,
+    decision : WithdrawalDecision,
+)-> Result<Withdrawal, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::DecideWithdrawal { withdrawal_id: withdrawal_id.to_string(), decision, resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+async fn get_portfolio (
+    tx : &Sender<Request>,
+    username : &str,
+)-> Result<Portfolio, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::GetPortfolio { username: username.to_string(), resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+async fn get_depth (
+    tx : &Sender<Request>,
+    market_id : &str,
+    levels : usize,
+)-> Result<MarketDepth, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::GetDepth { market_id: market_id.to_string(), levels, resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+async fn get_ticker (
+    tx : &Sender<Request>,
+    market_id : &str,
+)-> Result<Ticker, ExchangeError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::GetTicker { market_id: market_id.to_string(), resp: resp_tx };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
 async fn get_orderbook (
     tx : &Sender<Request>,
     market_id : &str,
-) -> Result<Orderbooks, String>{
+) -> Result<Orderbooks, ExchangeError>{
     let (resp_tx, resp_rx) = oneshot::channel();
     let req = Request::GetOrderbook { 
         market_id: market_id.to_string() , 
@@ -106,6 +357,57 @@ async fn get_orderbook (
     resp_rx.await.expect("Test worker response failed")
 }
 
+async fn resolve_market (
+    tx : &Sender<Request>,
+    username : &str,
+    market_id : &str,
+    winning_outcome : WinningOutcome,
+) -> Result<String, ExchangeError>{
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::ResolveMarket { 
+        username: username.to_string(), 
+        market_id: market_id.to_string() , 
+        winning_outcome,
+        resp: resp_tx 
+    };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+async fn cancel_order (
+    tx : &Sender<Request>,
+    username : &str,
+    order_id : &str,
+) -> Result<String, ExchangeError>{
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::CancelOrder { 
+        username: username.to_string(), 
+        order_id: order_id.to_string() , 
+        resp: resp_tx 
+    };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
+async fn amend_order (
+    tx : &Sender<Request>,
+    username : &str,
+    order_id : &str,
+    price : Option<u64>,
+    quantity : Option<u64>,
+) -> Result<OrderResult, ExchangeError>{
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let req = Request::AmendOrder { 
+        username: username.to_string(), 
+        order_id: order_id.to_string() , 
+        price,
+        quantity,
+        resp: resp_tx 
+    };
+    tx.send(req).await.expect("Test worker send failed");
+    resp_rx.await.expect("Test worker response failed")
+}
+
 #[tokio::test]
 async fn test_auth_flow() {
     //  Start the worker
@@ -128,12 +430,12 @@ async fn test_auth_flow() {
     assert!(res_dup.is_err());
     assert!(res_dup
         .unwrap_err()
-        .contains("Username already exists"));
+        == ExchangeError::UsernameTaken);
 
     //  Test signin with wrong password
     let res_wrong_pass = signin_user(&tx, "user1", "wrongpass").await;
     assert!(res_wrong_pass.is_err());
-    assert_eq!(res_wrong_pass.unwrap_err(), "Invalid password");
+    assert_eq!(res_wrong_pass.unwrap_err(), ExchangeError::InvalidCredentials);
 
     //  Test signin with correct password
     let res_correct_pass = signin_user(&tx, "user1", "pass123").await;
@@ -149,101 +451,1236 @@ async fn test_auth_flow() {
     //  Test signin with non-existent user
     let res_no_user = signin_user(&tx, "user_does_not_exist", "pass123").await;
     assert!(res_no_user.is_err());
-    assert_eq!(res_no_user.unwrap_err(), "User not found");
+    assert_eq!(res_no_user.unwrap_err(), ExchangeError::UserNotFound);
 
     //test create market 
-    let res_market = new_market(&tx, "user1", "market_name").await;
+    let res_market = new_market(&tx, "user1", "market_name", MarketOptions::default()).await;
     assert!(res_market.is_ok());
     let market_id = res_market.unwrap();
     assert!(!market_id.is_empty());
 
     // test create market with non-existing user 
-    let res_no_market = new_market(&tx, "user3", "market_name").await;
+    let res_no_market = new_market(&tx, "user3", "market_name", MarketOptions::default()).await;
     assert!(res_no_market.is_err());
 
     // test split stocks 
-    let res_split = split_stocks(&tx, "user2", &market_id, 100).await;
-    assert_eq!(res_split, Ok("Minted 100 of Stock A and B".to_string()));
+    let res_split = split_stocks(&tx, "user2", &market_id, 10).await;
+    assert_eq!(res_split, Ok("Minted 10 of every outcome".to_string()));
 
     // check user user holdings after split 
     let res_bal = get_user_details(&tx, "user2").await;
     assert!(res_bal.is_ok(), "Getting user details failed: {:?}", res_bal.err());
     let details2 = res_bal.unwrap();
-    assert!(details2.holdings.get(&market_id).is_some());
+    assert!(details2.holdings.contains_key(&market_id));
     let holdings = details2.holdings.get(&market_id).unwrap();
-    assert!(holdings.stock_a == 100 && holdings.stock_b == 100);
-    assert!(details2.balance == test_amount -100);
+    assert!(holdings.stock(&StockType::StockA) == 10 && holdings.stock(&StockType::StockB) == 10);
+    assert!(details2.balance == test_amount - 10 * 100);
 
     // split stocks for balance more than user's balance 
     let res_split = split_stocks(&tx, "user2", &market_id, test_amount).await;
-    assert_eq!(res_split, Err("Insufficient funds to mint".to_string()));
+    assert!(matches!(res_split, Err(ExchangeError::InsufficientFunds { .. })));
 
     // Get Orderbook (Empty) 
     let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
-    let stock_a_orderbook = orderbook.stock_a;
-    let stock_b_orderbook = orderbook.stock_b;
+    let stock_a_orderbook = &orderbook.books[0];
+    let stock_b_orderbook = &orderbook.books[1];
     assert!(stock_a_orderbook.buy.is_empty());
     assert!(stock_b_orderbook.buy.is_empty());
     assert!(stock_a_orderbook.sell.is_empty());
     assert!(stock_b_orderbook.sell.is_empty());
 
     // test create limit sell order 
-    let res_limit_sell = limit_order(&tx, "user2", StockType::StockA, 50, 10, &market_id, Ordertype::Sell).await;
-    assert_eq!(res_limit_sell , Ok("Order placed, waiting to be matched.".to_string()));
+    let res_limit_sell = limit_order(&tx, "user2", StockType::StockA, 50, 10, &market_id, Ordertype::Sell).await.map(|res| (res.status, res.remaining));
+    assert_eq!(res_limit_sell , Ok((OrderStatus::Resting, 10)));
 
     // create limit buy 
-    let res_limit_buy = limit_order(&tx, "user1", StockType::StockA, 40, 5, &market_id, Ordertype::Buy).await;
-    assert_eq!(res_limit_buy , Ok("Order placed, waiting to be matched.".to_string()));
+    let res_limit_buy = limit_order(&tx, "user1", StockType::StockA, 40, 5, &market_id, Ordertype::Buy).await.map(|res| (res.status, res.remaining));
+    assert_eq!(res_limit_buy , Ok((OrderStatus::Resting, 5)));
 
      //Verify Orderbook & Balances (Locked)
     let ob_after = get_orderbook(&tx, &market_id).await.unwrap();
-    let stock_a_ob = ob_after.stock_a;
+    let stock_a_ob = &ob_after.books[0];
     assert_eq!(stock_a_ob.buy.len(), 1); // user1's buy order
     assert_eq!(stock_a_ob.sell.len(), 1); // user2's sell order
 
     let u1_locked = get_user_details(&tx, "user1").await.unwrap();
     // user1's balance is locked
     assert_eq!(u1_locked.balance, 5000 - (40 * 5)); 
-    assert!(u1_locked.holdings.get(&market_id).is_none()); // stocks unchanged
+    assert!(!u1_locked.holdings.contains_key(&market_id)); // stocks unchanged
 
     let u2_locked = get_user_details(&tx, "user2").await.unwrap();
     // user2's stocks are locked
-    assert_eq!(u2_locked.balance, 4900); // balance unchanged
-    assert_eq!(u2_locked.holdings.get(&market_id).unwrap().stock_a, 90);
+    assert_eq!(u2_locked.balance, 4000); // balance unchanged
+    assert_eq!(u2_locked.holdings.get(&market_id).unwrap().stock(&StockType::StockA), 0);
 
     // limit buy order by user1 which will match the sell order 
-    let trades = limit_order(&tx, "user1", StockType::StockA, 60, 5, &market_id, Ordertype::Buy).await.unwrap();
-    assert!(trades.starts_with("[Trade"), "Expected a trade string, got: {}", trades);
-    assert!(trades.contains("from: \"user2\""));
-    assert!(trades.contains("to: \"user1\""));
-    assert!(trades.contains("trade_qty: 5"));
-    assert!(trades.contains("trade_price: 50"));
+    let res = limit_order(&tx, "user1", StockType::StockA, 60, 5, &market_id, Ordertype::Buy).await.unwrap();
+    assert_eq!(res.status, OrderStatus::Filled);
+    assert_eq!((res.filled, res.remaining, res.average_price), (5, 0, Some(50.0)));
+    assert_eq!(res.refunded, (60 - 50) * 5);
+    assert_eq!(res.trades.len(), 1);
+    assert_eq!((res.trades[0].from.as_str(), res.trades[0].to.as_str()), ("user2", "user1"));
+    assert_eq!((res.trades[0].trade_qty, res.trades[0].trade_price), (5, 50));
 
     // orderbook still contains sell of 5 A stocks from user2
     let ob_after = get_orderbook(&tx, &market_id).await.unwrap();
-    let stock_a_ob = ob_after.stock_a; 
+    let stock_a_ob = &ob_after.books[0]; 
     assert_eq!(stock_a_ob.sell.len(), 1);
 
     // checking balances and stocks after trade execution
     let u1 = get_user_details(&tx, "user1").await.unwrap();
     assert_eq!(u1.balance, 5000 - (40 * 5) - (50*5)); // 1 open buy order and 1 executed
-    assert_eq!(u1.holdings.get(&market_id).unwrap().stock_a , 5); // stock holdings increased
+    assert_eq!(u1.holdings.get(&market_id).unwrap().stock(&StockType::StockA) , 5); // stock holdings increased
 
     let u2 = get_user_details(&tx, "user2").await.unwrap();
-    assert_eq!(u2.balance, 4900 + (50*5)); // seller's balance increased after trade
+    assert_eq!(u2.balance, 4000 + (50*5)); // seller's balance increased after trade
 
     // limit buy order by user1 which will eat the sell orderbook
-    let trades = limit_order(&tx, "user1", StockType::StockA, 60, 10, &market_id, Ordertype::Buy).await.unwrap();
-    assert!(trades.starts_with("[Trade"), "Expected a trade string, got: {}", trades);
-    assert!(trades.contains("from: \"user2\""));
-    assert!(trades.contains("to: \"user1\""));
-    assert!(trades.contains("trade_qty: 5"));
-    assert!(trades.contains("trade_price: 50"));
+    let res = limit_order(&tx, "user1", StockType::StockA, 60, 10, &market_id, Ordertype::Buy).await.unwrap();
+    assert_eq!(res.status, OrderStatus::PartiallyFilled);
+    assert_eq!((res.filled, res.remaining, res.cancelled), (5, 5, 0));
+    assert_eq!((res.trades[0].from.as_str(), res.trades[0].to.as_str()), ("user2", "user1"));
+    assert_eq!((res.trades[0].trade_qty, res.trades[0].trade_price), (5, 50));
 
     // orderbook contains no sell orders
     let ob_after = get_orderbook(&tx, &market_id).await.unwrap();
-    let stock_a_ob = ob_after.stock_a; 
+    let stock_a_ob = &ob_after.books[0]; 
     assert_eq!(stock_a_ob.sell.len(), 0);
     assert_eq!(stock_a_ob.buy.len(), 2);  //another buy order placed for the 5 stocks
    
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_admin_account() {
+    let tx = spawn_worker(WorkerConfig { admin_username: Some("root".to_string()), admin_password: Some("admin123".to_string()), ..WorkerConfig::default() });
+
+    // the admin name can not be claimed, the configured account signs in with its own password
+    assert_eq!(signup_user(&tx, "root", "pass123").await, Err(ExchangeError::UsernameReserved));
+    assert_eq!(signin_user(&tx, "root", "pass123").await, Err(ExchangeError::InvalidCredentials));
+    assert_eq!(signin_user(&tx, "root", "admin123").await, Ok("root".to_string()));
+    assert_eq!(get_user_details(&tx, "root").await.unwrap().balance, 0);
+    assert!(check_solvency(&tx).await.unwrap().solvent);
+
+    // without a password the name is still reserved, but there is no account
+    let tx = spawn_worker(WorkerConfig { admin_username: Some("root".to_string()), ..WorkerConfig::default() });
+    assert_eq!(signup_user(&tx, "root", "pass123").await, Err(ExchangeError::UsernameReserved));
+    assert_eq!(signin_user(&tx, "root", "pass123").await, Err(ExchangeError::UserNotFound));
+}
+
+#[tokio::test]
+async fn test_resolve_market() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "creator", "pass123").await.unwrap();
+    signup_user(&tx, "trader", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "creator", "market_name", MarketOptions::default()).await.unwrap();
+
+    // trader mints 10 pairs and rests a sell, creator rests a buy
+    split_stocks(&tx, "trader", &market_id, 10).await.unwrap();
+    limit_order(&tx, "trader", StockType::StockA, 3, 10, &market_id, Ordertype::Sell).await.unwrap();
+    limit_order(&tx, "creator", StockType::StockB, 2, 5, &market_id, Ordertype::Buy).await.unwrap();
+    assert_eq!(get_user_details(&tx, "creator").await.unwrap().balance, 5000 - 10);
+
+    // only the creator can resolve
+    let res = resolve_market(&tx, "trader", &market_id, WinningOutcome::OutcomeA).await;
+    assert_eq!(res, Err(ExchangeError::NotMarketCreator));
+
+    let res = resolve_market(&tx, "creator", &market_id, WinningOutcome::OutcomeA).await;
+    assert!(res.is_ok(), "Resolving market failed: {:?}", res.err());
+
+    // resting orders are refunded and cleared from the book
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert!(orderbook.books[0].sell.is_empty());
+    assert!(orderbook.books[1].buy.is_empty());
+    let creator = get_user_details(&tx, "creator").await.unwrap();
+    assert_eq!(creator.balance, 5000);
+
+    // all 10 A shares (including the ones locked in the sell) pay out the full 100 each
+    let trader = get_user_details(&tx, "trader").await.unwrap();
+    assert_eq!(trader.balance, 4000 + 10 * 100);
+    assert!(!trader.holdings.contains_key(&market_id));
+
+    // a settled market can not be resolved again or traded
+    let res = resolve_market(&tx, "creator", &market_id, WinningOutcome::OutcomeB).await;
+    assert_eq!(res, Err(ExchangeError::MarketSettled));
+    let res = limit_order(&tx, "creator", StockType::StockA, 1, 1, &market_id, Ordertype::Buy).await;
+    assert_eq!(res, Err(ExchangeError::MarketSettled));
+}
+
+#[tokio::test]
+async fn test_split_merge_settled_market() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "creator", "pass123").await.unwrap();
+    signup_user(&tx, "trader", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "creator", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "trader", &market_id, 5).await.unwrap();
+    resolve_market(&tx, "creator", &market_id, WinningOutcome::OutcomeA).await.unwrap();
+
+    // a settled market neither mints nor redeems sets, its collateral was paid out
+    let res = split_stocks(&tx, "trader", &market_id, 1).await;
+    assert_eq!(res, Err(ExchangeError::MarketSettled));
+    let res = merge_stocks(&tx, "trader", &market_id, 1).await;
+    assert_eq!(res, Err(ExchangeError::MarketSettled));
+    let trader = get_user_details(&tx, "trader").await.unwrap();
+    assert_eq!(trader.balance, 5000);
+    assert!(check_solvency(&tx).await.unwrap().solvent);
+}
+
+#[tokio::test]
+async fn test_split_overflow() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "trader", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "trader", "market_name", MarketOptions::default()).await.unwrap();
+
+    // the cost of these sets wraps around u64 to less than the balance
+    let res = split_stocks(&tx, "trader", &market_id, 184467440737095517).await;
+    assert!(matches!(res, Err(ExchangeError::Validation(_))), "{:?}", res);
+    let res = merge_stocks(&tx, "trader", &market_id, 184467440737095517).await;
+    assert!(matches!(res, Err(ExchangeError::InsufficientStock { .. })), "{:?}", res);
+    let trader = get_user_details(&tx, "trader").await.unwrap();
+    assert_eq!(trader.balance, 5000);
+    let holdings = trader.holdings.get(&market_id).cloned().unwrap_or_default();
+    assert_eq!(holdings.stock(&StockType::StockA), 0);
+    assert_eq!(holdings.stock(&StockType::StockB), 0);
+    assert!(check_solvency(&tx).await.unwrap().solvent);
+}
+
+#[tokio::test]
+async fn test_cancel_order() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "other", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+
+    let buy = limit_order(&tx, "maker", StockType::StockA, 40, 5, &market_id, Ordertype::Buy).await.unwrap();
+    let sell = limit_order(&tx, "maker", StockType::StockB, 60, 4, &market_id, Ordertype::Sell).await.unwrap();
+    assert!(!buy.order_id.is_empty());
+    assert_ne!(buy.order_id, sell.order_id);
+    let maker = get_user_details(&tx, "maker").await.unwrap();
+    assert_eq!(maker.balance, 4000 - 40 * 5);
+    assert_eq!(maker.holdings.get(&market_id).unwrap().stock(&StockType::StockB), 6);
+
+    // orders can only be cancelled by their owner
+    let res = cancel_order(&tx, "other", &buy.order_id).await;
+    assert_eq!(res, Err(ExchangeError::OrderNotFound));
+
+    // cancelling releases the locked funds and stock
+    cancel_order(&tx, "maker", &buy.order_id).await.unwrap();
+    cancel_order(&tx, "maker", &sell.order_id).await.unwrap();
+    let maker = get_user_details(&tx, "maker").await.unwrap();
+    assert_eq!(maker.balance, 4000);
+    assert_eq!(maker.holdings.get(&market_id).unwrap().stock(&StockType::StockB), 10);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert!(orderbook.books[0].buy.is_empty());
+    assert!(orderbook.books[1].sell.is_empty());
+
+    let res = cancel_order(&tx, "maker", &buy.order_id).await;
+    assert_eq!(res, Err(ExchangeError::OrderNotFound));
+}
+
+#[tokio::test]
+async fn test_amend_order() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "taker", &market_id, 10).await.unwrap();
+
+    let first = limit_order(&tx, "maker", StockType::StockA, 40, 10, &market_id, Ordertype::Buy).await.unwrap();
+    let second = limit_order(&tx, "maker", StockType::StockA, 40, 10, &market_id, Ordertype::Buy).await.unwrap();
+
+    // reducing quantity keeps priority and releases only the difference
+    amend_order(&tx, "maker", &second.order_id, None, Some(4)).await.unwrap();
+    amend_order(&tx, "maker", &first.order_id, None, Some(6)).await.unwrap();
+    assert_eq!(get_user_details(&tx, "maker").await.unwrap().balance, 5000 - 40 * 10);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    let level = orderbook.books[0].buy.get(&40).unwrap();
+    assert_eq!(level[0].order_id, first.order_id);
+    assert_eq!(level[0].quantity, 6);
+    assert_eq!(level[1].quantity, 4);
+
+    // increasing quantity moves the order to the back of the queue and locks only the extra funds
+    amend_order(&tx, "maker", &first.order_id, None, Some(8)).await.unwrap();
+    assert_eq!(get_user_details(&tx, "maker").await.unwrap().balance, 5000 - 40 * 12);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert_eq!(orderbook.books[0].buy.get(&40).unwrap()[1].order_id, first.order_id);
+
+    // repricing re-matches the order against the book
+    let ask = limit_order(&tx, "taker", StockType::StockA, 45, 3, &market_id, Ordertype::Sell).await.unwrap();
+    let res = amend_order(&tx, "maker", &second.order_id, Some(50), None).await.unwrap();
+    assert_eq!(res.order_id, second.order_id);
+    assert_eq!((res.status, res.filled, res.remaining, res.refunded), (OrderStatus::PartiallyFilled, 3, 1, 5 * 3));
+    assert_eq!((res.trades[0].trade_qty, res.trades[0].trade_price), (3, 45));
+    let maker = get_user_details(&tx, "maker").await.unwrap();
+    // 8 @ 40 resting, 1 @ 50 resting and 3 bought @ 45
+    assert_eq!(maker.balance, 5000 - 40 * 8 - 50 - 45 * 3);
+    assert_eq!(maker.holdings.get(&market_id).unwrap().stock(&StockType::StockA), 3);
+
+    // filled orders can not be amended, resting ones only within the user's funds and holdings
+    let res = amend_order(&tx, "taker", &ask.order_id, None, Some(5)).await;
+    assert_eq!(res, Err(ExchangeError::OrderNotFound));
+    let ask = limit_order(&tx, "taker", StockType::StockA, 90, 5, &market_id, Ordertype::Sell).await.unwrap();
+    let res = amend_order(&tx, "taker", &ask.order_id, None, Some(200)).await;
+    assert!(matches!(res, Err(ExchangeError::InsufficientStock { .. })));
+    let res = amend_order(&tx, "maker", &first.order_id, None, Some(1000)).await;
+    assert!(matches!(res, Err(ExchangeError::InsufficientFunds { .. })));
+}
+
+#[tokio::test]
+async fn test_time_in_force() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 50, 10, &market_id, Ordertype::Sell).await.unwrap();
+
+    // fill-or-kill that can not fully fill leaves book and balance untouched
+    let res = limit_order_with(&tx, "taker", StockType::StockA, 50, 11, &market_id, Ordertype::Buy, TimeInForce::FillOrKill, PostOnly::Disabled, SelfTradePrevention::CancelNewest, None).await.unwrap();
+    assert_eq!((res.status, res.filled, res.cancelled), (OrderStatus::Cancelled, 0, 11));
+    assert_eq!(get_user_details(&tx, "taker").await.unwrap().balance, 5000);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert_eq!(orderbook.books[0].sell.get(&50).unwrap()[0].quantity, 10);
+
+    // immediate-or-cancel fills what it can and refunds the rest
+    let res = limit_order_with(&tx, "taker", StockType::StockA, 60, 15, &market_id, Ordertype::Buy, TimeInForce::ImmediateOrCancel, PostOnly::Disabled, SelfTradePrevention::CancelNewest, None).await.unwrap();
+    assert_eq!((res.status, res.filled, res.cancelled, res.remaining), (OrderStatus::PartiallyFilledCancelled, 10, 5, 0));
+    let taker = get_user_details(&tx, "taker").await.unwrap();
+    assert_eq!(taker.balance, 5000 - 50 * 10);
+    assert_eq!(taker.holdings.get(&market_id).unwrap().stock(&StockType::StockA), 10);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert!(orderbook.books[0].sell.is_empty());
+    assert!(orderbook.books[0].buy.is_empty());
+
+    // good-till-date orders must expire in the future
+    let expired = chrono::Utc::now() - chrono::Duration::seconds(1);
+    let res = limit_order_with(&tx, "taker", StockType::StockB, 30, 5, &market_id, Ordertype::Buy, TimeInForce::GoodTillDate(expired), PostOnly::Disabled, SelfTradePrevention::CancelNewest, None).await;
+    assert_eq!(res, Err(ExchangeError::Validation("Expiry time must be in the future".to_string())));
+
+    // and are refunded once their expiry passes
+    let expiry = chrono::Utc::now() + chrono::Duration::milliseconds(300);
+    limit_order_with(&tx, "taker", StockType::StockB, 30, 5, &market_id, Ordertype::Buy, TimeInForce::GoodTillDate(expiry), PostOnly::Disabled, SelfTradePrevention::CancelNewest, None).await.unwrap();
+    assert_eq!(get_user_details(&tx, "taker").await.unwrap().balance, 5000 - 500 - 150);
+    tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+    assert_eq!(get_user_details(&tx, "taker").await.unwrap().balance, 5000 - 500);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert!(orderbook.books[1].buy.is_empty());
+}
+
+#[tokio::test]
+async fn test_post_only() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "quoter", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 50, 10, &market_id, Ordertype::Sell).await.unwrap();
+
+    // a crossing post-only order is rejected without locking anything
+    let res = limit_order_with(&tx, "quoter", StockType::StockA, 55, 5, &market_id, Ordertype::Buy, TimeInForce::GoodTillCancel, PostOnly::Reject, SelfTradePrevention::CancelNewest, None).await;
+    assert_eq!(res, Err(ExchangeError::PostOnlyWouldTrade));
+    assert_eq!(get_user_details(&tx, "quoter").await.unwrap().balance, 5000);
+
+    // or repriced one tick behind the best ask
+    let res = limit_order_with(&tx, "quoter", StockType::StockA, 55, 5, &market_id, Ordertype::Buy, TimeInForce::GoodTillCancel, PostOnly::Reprice, SelfTradePrevention::CancelNewest, None).await.unwrap();
+    assert_eq!(res.status, OrderStatus::Resting);
+    assert_eq!(get_user_details(&tx, "quoter").await.unwrap().balance, 5000 - 49 * 5);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert_eq!(orderbook.books[0].buy.get(&49).unwrap()[0].order_id, res.order_id);
+    assert_eq!(orderbook.books[0].sell.get(&50).unwrap()[0].quantity, 10);
+
+    // a non crossing post-only order rests at its own price, amending it across the book is checked again
+    let res = limit_order_with(&tx, "quoter", StockType::StockA, 40, 5, &market_id, Ordertype::Buy, TimeInForce::GoodTillCancel, PostOnly::Reject, SelfTradePrevention::CancelNewest, None).await.unwrap();
+    let amended = amend_order(&tx, "quoter", &res.order_id, Some(52), None).await;
+    assert_eq!(amended, Err(ExchangeError::PostOnlyWouldTrade));
+
+    let res = limit_order_with(&tx, "quoter", StockType::StockA, 40, 5, &market_id, Ordertype::Buy, TimeInForce::ImmediateOrCancel, PostOnly::Reject, SelfTradePrevention::CancelNewest, None).await;
+    assert!(res.is_err());
+}
+
+#[tokio::test]
+async fn test_self_trade_prevention() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "creator", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "creator", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 50, 10, &market_id, Ordertype::Sell).await.unwrap();
+
+    // cancel newest: the incoming buy is cancelled and refunded, the resting sell stays
+    let res = limit_order_with(&tx, "maker", StockType::StockA, 60, 5, &market_id, Ordertype::Buy, TimeInForce::GoodTillCancel, PostOnly::Disabled, SelfTradePrevention::CancelNewest, None).await.unwrap();
+    assert_eq!((res.status, res.filled, res.cancelled), (OrderStatus::Cancelled, 0, 5));
+    assert_eq!(get_user_details(&tx, "maker").await.unwrap().balance, 4000);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert_eq!(orderbook.books[0].sell.get(&50).unwrap()[0].quantity, 10);
+    assert!(orderbook.books[0].buy.is_empty());
+
+    // cancel oldest: the resting sell is cancelled, its stock released and the buy rests instead
+    let res = limit_order_with(&tx, "maker", StockType::StockA, 60, 5, &market_id, Ordertype::Buy, TimeInForce::GoodTillCancel, PostOnly::Disabled, SelfTradePrevention::CancelOldest, None).await.unwrap();
+    assert_eq!((res.status, res.remaining), (OrderStatus::Resting, 5));
+    let maker = get_user_details(&tx, "maker").await.unwrap();
+    assert_eq!(maker.balance, 4000 - 60 * 5);
+    assert_eq!(maker.holdings.get(&market_id).unwrap().stock(&StockType::StockA), 10);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert!(orderbook.books[0].sell.is_empty());
+
+    // decrement and cancel on a market order: the resting buy shrinks by the market order's size
+    let res = market_order(&tx, "maker", StockType::StockA, 2, &market_id, Ordertype::Sell, SelfTradePrevention::DecrementAndCancel).await.unwrap();
+    assert_eq!((res.status, res.filled, res.cancelled), (OrderStatus::Cancelled, 0, 2));
+    let maker = get_user_details(&tx, "maker").await.unwrap();
+    assert_eq!(maker.balance, 4000 - 60 * 3);
+    assert_eq!(maker.holdings.get(&market_id).unwrap().stock(&StockType::StockA), 10);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert_eq!(orderbook.books[0].buy.get(&60).unwrap()[0].quantity, 3);
+
+    // other users still trade with the order
+    split_stocks(&tx, "creator", &market_id, 10).await.unwrap();
+    let res = market_order(&tx, "creator", StockType::StockA, 3, &market_id, Ordertype::Sell, SelfTradePrevention::CancelNewest).await.unwrap();
+    assert_eq!((res.status, res.filled, res.average_price), (OrderStatus::Filled, 3, Some(60.0)));
+    let maker = get_user_details(&tx, "maker").await.unwrap();
+    assert_eq!(maker.holdings.get(&market_id).unwrap().stock(&StockType::StockA), 13);
+}
+
+#[tokio::test]
+async fn test_cross_outcome_matching() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "alice", "pass123").await.unwrap();
+    signup_user(&tx, "bob", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "alice", "market_name", MarketOptions::default()).await.unwrap();
+
+    // a buy of A at 60 and a buy of B at 45 fund new pairs, B trades at 100 - 60
+    limit_order(&tx, "alice", StockType::StockA, 60, 5, &market_id, Ordertype::Buy).await.unwrap();
+    let res = limit_order(&tx, "bob", StockType::StockB, 45, 5, &market_id, Ordertype::Buy).await.unwrap();
+    assert_eq!(res.trades[0].kind, TradeKind::Mint);
+    assert_eq!(res.trades[0].trade_price, 40);
+    assert_eq!(res.refunded, 5 * 5);
+    let alice = get_user_details(&tx, "alice").await.unwrap();
+    assert_eq!(alice.balance, 5000 - 60 * 5);
+    assert_eq!(alice.holdings.get(&market_id).unwrap().stock(&StockType::StockA), 5);
+    let bob = get_user_details(&tx, "bob").await.unwrap();
+    assert_eq!(bob.balance, 5000 - 40 * 5);
+    assert_eq!(bob.holdings.get(&market_id).unwrap().stock(&StockType::StockB), 5);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert!(orderbook.books[0].buy.is_empty());
+    assert!(orderbook.books[1].buy.is_empty());
+
+    // a sell of A at 30 and a sell of B at 65 burn pairs, the collateral is split by price
+    limit_order(&tx, "alice", StockType::StockA, 30, 5, &market_id, Ordertype::Sell).await.unwrap();
+    let res = market_order(&tx, "bob", StockType::StockB, 3, &market_id, Ordertype::Sell, SelfTradePrevention::CancelNewest).await.unwrap();
+    assert_eq!(res.trades[0].kind, TradeKind::Burn);
+    assert_eq!(res.trades[0].trade_price, 70);
+    assert_eq!(get_user_details(&tx, "alice").await.unwrap().balance, 5000 - 60 * 5 + 30 * 3);
+    let bob = get_user_details(&tx, "bob").await.unwrap();
+    assert_eq!(bob.balance, 5000 - 40 * 5 + 70 * 3);
+    assert_eq!(bob.holdings.get(&market_id).unwrap().stock(&StockType::StockB), 2);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert_eq!(orderbook.books[0].sell.get(&30).unwrap()[0].quantity, 2);
+
+    // the order's own book wins on equal prices
+    signup_user(&tx, "carol", "pass567").await.unwrap();
+    limit_order(&tx, "alice", StockType::StockA, 25, 2, &market_id, Ordertype::Buy).await.unwrap();
+    limit_order(&tx, "bob", StockType::StockB, 75, 2, &market_id, Ordertype::Sell).await.unwrap();
+    let res = limit_order(&tx, "carol", StockType::StockB, 75, 2, &market_id, Ordertype::Buy).await.unwrap();
+    assert_eq!(res.trades[0].kind, TradeKind::Transfer);
+    assert_eq!(res.trades[0].from, "bob");
+}
+
+#[tokio::test]
+async fn test_price_validation() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions::default()).await.unwrap();
+
+    // prices lie strictly between 0 and the pair payout of 100
+    for price in [0, 100, 250] {
+        let res = limit_order(&tx, "maker", StockType::StockA, price, 1, &market_id, Ordertype::Buy).await;
+        assert_eq!(res, Err(ExchangeError::Validation("Price must be between 0 and 100 (exclusive)".to_string())));
+    }
+    assert_eq!(get_user_details(&tx, "maker").await.unwrap().balance, 5000);
+    let order = limit_order(&tx, "maker", StockType::StockA, 99, 1, &market_id, Ordertype::Buy).await.unwrap();
+    let res = amend_order(&tx, "maker", &order.order_id, Some(100), None).await;
+    assert_eq!(res, Err(ExchangeError::Validation("Price must be between 0 and 100 (exclusive)".to_string())));
+
+    // the tick size has to divide the payout
+    let res = new_market(&tx, "maker", "market_name", MarketOptions { tick_size: Some(3), ..Default::default() }).await;
+    assert_eq!(res, Err(ExchangeError::Validation("Tick size must divide the pair payout of 100".to_string())));
+    let res = new_market(&tx, "maker", "market_name", MarketOptions { tick_size: Some(0), ..Default::default() }).await;
+    assert!(res.is_err());
+
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions { tick_size: Some(5), ..Default::default() }).await.unwrap();
+    let res = limit_order(&tx, "maker", StockType::StockA, 42, 1, &market_id, Ordertype::Buy).await;
+    assert_eq!(res, Err(ExchangeError::Validation("Price must be a multiple of the tick size 5".to_string())));
+    limit_order(&tx, "maker", StockType::StockA, 45, 1, &market_id, Ordertype::Buy).await.unwrap();
+
+    // post-only orders are repriced by a whole tick, the bid on A at 45 is an ask on B at 55
+    split_stocks(&tx, "maker", &market_id, 1).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockB, 60, 1, &market_id, Ordertype::Sell).await.unwrap();
+    let res = limit_order_with(&tx, "maker", StockType::StockB, 60, 1, &market_id, Ordertype::Buy, TimeInForce::GoodTillCancel, PostOnly::Reprice, SelfTradePrevention::CancelNewest, None).await.unwrap();
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert_eq!(orderbook.books[1].buy.get(&50).unwrap()[0].order_id, res.order_id);
+}
+
+#[tokio::test]
+async fn test_zero_quantity() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "trader", "pass123").await.unwrap();
+    let market_id = new_market(&tx, "trader", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "trader", &market_id, 5).await.unwrap();
+
+    // empty orders are rejected instead of reporting an empty fill and leaving a reservation behind
+    let zero_quantity = ExchangeError::Validation("Quantity must be greater than zero".to_string());
+    for ordertype in [Ordertype::Buy, Ordertype::Sell] {
+        assert_eq!(limit_order(&tx, "trader", StockType::StockA, 40, 0, &market_id, ordertype.clone()).await, Err(zero_quantity.clone()));
+        assert_eq!(market_order(&tx, "trader", StockType::StockA, 0, &market_id, ordertype.clone(), SelfTradePrevention::CancelNewest).await, Err(zero_quantity.clone()));
+        assert_eq!(stop_order(&tx, "trader", StockType::StockA, 0, &market_id, ordertype, 60, None).await, Err(zero_quantity.clone()));
+    }
+    let trader = get_user_details(&tx, "trader").await.unwrap();
+    assert!(trader.reservations.is_empty());
+    assert_eq!(trader.reserved_balance, 0);
+    assert!(check_solvency(&tx).await.unwrap().solvent);
+}
+
+#[tokio::test]
+async fn test_market_order_slippage() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 40, 3, &market_id, Ordertype::Sell).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 50, 3, &market_id, Ordertype::Sell).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 70, 3, &market_id, Ordertype::Sell).await.unwrap();
+
+    // the sweep stops before the first level above max_price
+    let limits = SlippageLimits { max_price: Some(50), ..Default::default() };
+    let res = market_order_with(&tx, "taker", StockType::StockA, 8, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest, limits, false).await.unwrap();
+    assert_eq!(res.cancelled, 2);
+    let taker = get_user_details(&tx, "taker").await.unwrap();
+    assert_eq!(taker.balance, 5000 - 40 * 3 - 50 * 3);
+    assert_eq!(taker.holdings.get(&market_id).unwrap().stock(&StockType::StockA), 6);
+
+    // and spends no more than max_total_cost
+    let limits = SlippageLimits { max_total_cost: Some(150), ..Default::default() };
+    let res = market_order_with(&tx, "taker", StockType::StockA, 3, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest, limits, false).await.unwrap();
+    assert_eq!(res.cancelled, 1);
+    assert_eq!(get_user_details(&tx, "taker").await.unwrap().balance, 5000 - 270 - 70 * 2);
+
+    // sells stop at min_price and keep the unfilled stock
+    limit_order(&tx, "maker", StockType::StockA, 30, 2, &market_id, Ordertype::Buy).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 20, 2, &market_id, Ordertype::Buy).await.unwrap();
+    let limits = SlippageLimits { min_price: Some(25), ..Default::default() };
+    let res = market_order_with(&tx, "taker", StockType::StockA, 5, &market_id, Ordertype::Sell, SelfTradePrevention::CancelNewest, limits, false).await.unwrap();
+    assert_eq!(res.cancelled, 3);
+    assert_eq!(get_user_details(&tx, "taker").await.unwrap().holdings.get(&market_id).unwrap().stock(&StockType::StockA), 8 - 2);
+
+    // limits have to match the order side
+    let limits = SlippageLimits { min_price: Some(25), ..Default::default() };
+    let res = market_order_with(&tx, "taker", StockType::StockA, 1, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest, limits, false).await;
+    assert_eq!(res, Err(ExchangeError::Validation("min_price only applies to sell orders".to_string())));
+}
+
+#[tokio::test]
+async fn test_quote_market_order() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 40, 3, &market_id, Ordertype::Sell).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 50, 3, &market_id, Ordertype::Sell).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 70, 3, &market_id, Ordertype::Sell).await.unwrap();
+
+    let limits = SlippageLimits { max_price: Some(50), ..Default::default() };
+    let res = quote(&tx, "taker", StockType::StockA, 8, &market_id, Ordertype::Buy, limits).await.unwrap();
+    assert_eq!(res, Quote {
+        fills: vec![QuoteLevel { price: 40, quantity: 3 }, QuoteLevel { price: 50, quantity: 3 }],
+        filled: 6,
+        average_price: Some(45.0),
+        total_cost: 270,
+        unfilled: 2,
+        amm_filled: 0,
+        amm_cost: 0,
+    });
+
+    // quoting changes nothing
+    assert_eq!(get_user_details(&tx, "taker").await.unwrap().balance, 5000);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert_eq!(orderbook.books[0].sell.get(&40).unwrap()[0].quantity, 3);
+
+    // and matches what the market order then does
+    let res = market_order_with(&tx, "taker", StockType::StockA, 8, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest, limits, false).await.unwrap();
+    assert_eq!(res.cancelled, 2);
+    assert_eq!(get_user_details(&tx, "taker").await.unwrap().balance, 5000 - 270);
+
+    // an empty book quotes nothing, sells are checked against the holdings
+    let res = quote(&tx, "taker", StockType::StockB, 5, &market_id, Ordertype::Buy, SlippageLimits::default()).await.unwrap();
+    assert_eq!(res.average_price, None);
+    assert_eq!(res.unfilled, 5);
+    let res = quote(&tx, "taker", StockType::StockA, 10, &market_id, Ordertype::Sell, SlippageLimits::default()).await;
+    assert_eq!(res, Err(ExchangeError::InsufficientStock { required : 10, available : 6 }));
+}
+
+#[tokio::test]
+async fn test_all_or_nothing_market_order() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 40, 3, &market_id, Ordertype::Sell).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 60, 3, &market_id, Ordertype::Sell).await.unwrap();
+
+    // an all-or-nothing buy that can not fully fill changes nothing
+    let res = market_order_with(&tx, "taker", StockType::StockA, 7, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest, SlippageLimits::default(), true).await.unwrap();
+    assert_eq!((res.status, res.cancelled), (OrderStatus::Cancelled, 7));
+    let limits = SlippageLimits { max_total_cost: Some(299), ..Default::default() };
+    let res = market_order_with(&tx, "taker", StockType::StockA, 6, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest, limits, true).await.unwrap();
+    assert_eq!((res.status, res.cancelled), (OrderStatus::Cancelled, 6));
+    assert_eq!(get_user_details(&tx, "taker").await.unwrap().balance, 5000);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert_eq!(orderbook.books[0].sell.get(&40).unwrap()[0].quantity, 3);
+    assert_eq!(orderbook.books[0].sell.get(&60).unwrap()[0].quantity, 3);
+
+    // one that can is filled at exactly the planned cost
+    let res = market_order_with(&tx, "taker", StockType::StockA, 6, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest, SlippageLimits::default(), true).await.unwrap();
+    assert_eq!((res.status, res.filled, res.average_price), (OrderStatus::Filled, 6, Some(50.0)));
+    let taker = get_user_details(&tx, "taker").await.unwrap();
+    assert_eq!(taker.balance, 5000 - 40 * 3 - 60 * 3);
+    assert_eq!(taker.holdings.get(&market_id).unwrap().stock(&StockType::StockA), 6);
+
+    // a buy running out of funds leaves the level it stopped at in the book
+    split_stocks(&tx, "taker", &market_id, 45).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 90, 4, &market_id, Ordertype::Sell).await.unwrap();
+    let res = market_order(&tx, "taker", StockType::StockA, 4, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest).await.unwrap();
+    assert_eq!(res.cancelled, 2);
+    assert_eq!(get_user_details(&tx, "taker").await.unwrap().balance, 200 - 90 * 2);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert_eq!(orderbook.books[0].sell.get(&90).unwrap()[0].quantity, 2);
+}
+
+#[tokio::test]
+async fn test_stop_orders() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "trader", "pass345").await.unwrap();
+    signup_user(&tx, "other", "pass567").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+    split_stocks(&tx, "trader", &market_id, 10).await.unwrap();
+
+    // a stop-loss sell locks its stock until triggered
+    let stop = stop_order(&tx, "trader", StockType::StockA, 4, &market_id, Ordertype::Sell, 40, None).await.unwrap();
+    assert_eq!(stop.msg, "Stop order placed, triggers at 40.");
+    assert_eq!(get_user_details(&tx, "trader").await.unwrap().holdings.get(&market_id).unwrap().stock(&StockType::StockA), 6);
+    let open = get_open_orders(&tx, "trader").await.unwrap();
+    assert_eq!((open[0].price, open[0].locked_balance, open[0].locked_stock), (1, 0, 4));
+
+    // a trade at 38 triggers it and it sells into the remaining bids as a market order
+    limit_order(&tx, "other", StockType::StockA, 38, 4, &market_id, Ordertype::Buy).await.unwrap();
+    market_order(&tx, "maker", StockType::StockA, 1, &market_id, Ordertype::Sell, SelfTradePrevention::CancelNewest).await.unwrap();
+    let trader = get_user_details(&tx, "trader").await.unwrap();
+    assert_eq!(trader.balance, 4000 + 38 * 3);
+    assert_eq!(trader.holdings.get(&market_id).unwrap().stock(&StockType::StockA), 7);    // one share found no bid
+    assert_eq!(get_user_details(&tx, "other").await.unwrap().holdings.get(&market_id).unwrap().stock(&StockType::StockA), 4);
+    let res = cancel_order(&tx, "trader", &stop.order_id).await;
+    assert_eq!(res, Err(ExchangeError::OrderNotFound));
+
+    // stops that the last trade already crossed are rejected
+    let res = stop_order(&tx, "trader", StockType::StockA, 1, &market_id, Ordertype::Sell, 50, None).await;
+    assert_eq!(res, Err(ExchangeError::StopWouldTrigger));
+
+    // a stop-limit buy locks its limit price and is refunded when cancelled
+    let stop = stop_order(&tx, "trader", StockType::StockA, 2, &market_id, Ordertype::Buy, 60, Some(65)).await.unwrap();
+    assert_eq!(get_user_details(&tx, "trader").await.unwrap().balance, 4114 - 65 * 2);
+    cancel_order(&tx, "trader", &stop.order_id).await.unwrap();
+    assert_eq!(get_user_details(&tx, "trader").await.unwrap().balance, 4114);
+
+    // once triggered it rests in the book as a limit order under the same id
+    let stop = stop_order(&tx, "trader", StockType::StockA, 2, &market_id, Ordertype::Buy, 45, Some(50)).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 45, 1, &market_id, Ordertype::Sell).await.unwrap();
+    limit_order(&tx, "other", StockType::StockA, 45, 1, &market_id, Ordertype::Buy).await.unwrap();
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert_eq!(orderbook.books[0].buy.get(&50).unwrap()[0].order_id, stop.order_id);
+}
+
+#[tokio::test]
+async fn test_iceberg_orders() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "other", "pass345").await.unwrap();
+    signup_user(&tx, "taker", "pass567").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+    split_stocks(&tx, "other", &market_id, 10).await.unwrap();
+
+    // only the displayed slice shows in the book, the whole order is locked
+    let iceberg = limit_order_with(&tx, "maker", StockType::StockA, 50, 9, &market_id, Ordertype::Sell, TimeInForce::GoodTillCancel, PostOnly::Disabled, SelfTradePrevention::CancelNewest, Some(3)).await.unwrap();
+    limit_order(&tx, "other", StockType::StockA, 50, 2, &market_id, Ordertype::Sell).await.unwrap();
+    assert_eq!(get_user_details(&tx, "maker").await.unwrap().holdings.get(&market_id).unwrap().stock(&StockType::StockA), 1);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    let level = orderbook.books[0].sell.get(&50).unwrap();
+    assert_eq!(level[0].order_id, iceberg.order_id);
+    assert_eq!(level[0].quantity, 3);
+
+    // a filled slice is replenished from the reserve and queues behind the rest of the level
+    let res = market_order(&tx, "taker", StockType::StockA, 4, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest).await.unwrap();
+    let sellers : Vec<&str> = res.trades.iter().map(|trade| trade.from.as_str()).collect();
+    assert_eq!(sellers, vec!["maker", "other"]);
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    let level = orderbook.books[0].sell.get(&50).unwrap();
+    assert_eq!(level[0].quantity, 1);
+    assert_eq!(level[1].order_id, iceberg.order_id);
+    assert_eq!(level[1].quantity, 3);
+
+    // a large taker trades through the reserve, fill-or-kill counts it as well
+    let res = limit_order_with(&tx, "taker", StockType::StockA, 50, 6, &market_id, Ordertype::Buy, TimeInForce::FillOrKill, PostOnly::Disabled, SelfTradePrevention::CancelNewest, None).await.unwrap();
+    assert_eq!((res.status, res.filled), (OrderStatus::Filled, 6));
+    assert_eq!(get_user_details(&tx, "taker").await.unwrap().holdings.get(&market_id).unwrap().stock(&StockType::StockA), 10);
+
+    // cancelling releases the displayed slice and the hidden reserve
+    let res = cancel_order(&tx, "maker", &iceberg.order_id).await.unwrap();
+    assert_eq!(res, format!("Order {} cancelled, 1 unfilled released", iceberg.order_id));
+    assert_eq!(get_user_details(&tx, "maker").await.unwrap().holdings.get(&market_id).unwrap().stock(&StockType::StockA), 2);
+}
+
+#[tokio::test]
+async fn test_depth_and_ticker() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 40, 2, &market_id, Ordertype::Buy).await.unwrap();
+    limit_order(&tx, "taker", StockType::StockA, 40, 3, &market_id, Ordertype::Buy).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 35, 1, &market_id, Ordertype::Buy).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 44, 4, &market_id, Ordertype::Sell).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 50, 1, &market_id, Ordertype::Sell).await.unwrap();
+
+    // levels are aggregated and cut off best first
+    let depth = get_depth(&tx, &market_id, 1).await.unwrap();
+    assert_eq!(depth.books[0].bids, vec![DepthLevel { price: 40, quantity: 5 }]);
+    assert_eq!(depth.books[0].asks, vec![DepthLevel { price: 44, quantity: 4 }]);
+    assert!(depth.books[1].bids.is_empty());
+    let depth = get_depth(&tx, &market_id, 10).await.unwrap();
+    assert_eq!(depth.books[0].bids[1], DepthLevel { price: 35, quantity: 1 });
+    assert_eq!(depth.books[0].asks.len(), 2);
+
+    let ticker = get_ticker(&tx, &market_id).await.unwrap();
+    assert_eq!(ticker.tickers[0].best_bid, Some(40));
+    assert_eq!(ticker.tickers[0].best_ask, Some(44));
+    assert_eq!(ticker.tickers[0].spread, Some(4));
+    assert_eq!(ticker.tickers[0].mid, Some(42.0));
+    assert_eq!(ticker.tickers[0].last_price, None);
+
+    // a trade sets the last price of both outcomes once it mints
+    market_order(&tx, "taker", StockType::StockA, 1, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest).await.unwrap();
+    limit_order(&tx, "taker", StockType::StockB, 60, 1, &market_id, Ordertype::Buy).await.unwrap();
+    let ticker = get_ticker(&tx, &market_id).await.unwrap();
+    assert_eq!(ticker.tickers[0].last_price, Some(40.0));
+    assert_eq!(ticker.tickers[1].last_price, Some(60.0));
+    assert_eq!(ticker.tickers[1].best_bid, None);
+
+    assert_eq!(get_ticker(&tx, "missing").await, Err(ExchangeError::MarketNotFound));
+}
+
+#[tokio::test]
+async fn test_open_orders() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    let first_market = new_market(&tx, "maker", "first", MarketOptions::default()).await.unwrap();
+    let second_market = new_market(&tx, "maker", "second", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "maker", &first_market, 10).await.unwrap();
+
+    let iceberg = limit_order_with(&tx, "maker", StockType::StockA, 50, 8, &first_market, Ordertype::Sell, TimeInForce::GoodTillCancel, PostOnly::Disabled, SelfTradePrevention::CancelNewest, Some(2)).await.unwrap();
+    let bid = limit_order(&tx, "maker", StockType::StockB, 30, 4, &second_market, Ordertype::Buy).await.unwrap();
+    let stop = stop_order(&tx, "maker", StockType::StockB, 2, &first_market, Ordertype::Sell, 20, Some(15)).await.unwrap();
+    market_order(&tx, "taker", StockType::StockA, 3, &first_market, Ordertype::Buy, SelfTradePrevention::CancelNewest).await.unwrap();
+
+    // the public book shows neither the owner nor the hidden reserve
+    let orderbook = get_orderbook(&tx, &first_market).await.unwrap();
+    let level = orderbook.books[0].sell.get(&50).unwrap();
+    assert_eq!(level[0].order_id, iceberg.order_id);
+    assert_eq!(level[0].quantity, 1);
+
+    // the owner sees every open order across markets
+    let orders = get_open_orders(&tx, "maker").await.unwrap();
+    assert_eq!(orders.len(), 3);
+    let find = |orders: &[OpenOrder], order_id: &str| orders.iter().find(|order| order.order_id == order_id).cloned().unwrap();
+    let open = find(&orders, &iceberg.order_id);
+    assert_eq!(open.market_id, first_market);
+    assert_eq!(open.remaining_quantity, 5);
+    assert_eq!(open.original_quantity, 8);
+    assert_eq!(open.displayed_quantity, 1);
+    assert_eq!(open.locked_stock, 5);
+    let open = find(&orders, &bid.order_id);
+    assert_eq!(open.market_id, second_market);
+    assert_eq!(open.locked_balance, 30 * 4);
+    let open = find(&orders, &stop.order_id);
+    assert_eq!(open.trigger_price, Some(20));
+    assert_eq!(open.locked_stock, 2);
+
+    // amending keeps what was already filled in the original quantity
+    amend_order(&tx, "maker", &iceberg.order_id, None, Some(4)).await.unwrap();
+    let open = find(&get_open_orders(&tx, "maker").await.unwrap(), &iceberg.order_id);
+    assert_eq!(open.remaining_quantity, 4);
+    assert_eq!(open.original_quantity, 7);
+
+    assert!(get_open_orders(&tx, "taker").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_multi_outcome_market() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    assert_eq!(new_market(&tx, "maker", "single", MarketOptions { outcomes: Some(&["Yes"]), ..Default::default() }).await, Err(ExchangeError::Validation("A market needs at least two outcomes".to_string())));
+    let market_id = new_market(&tx, "maker", "election", MarketOptions { outcomes: Some(&["Alice", "Bob", "Carol"]), ..Default::default() }).await.unwrap();
+
+    // a split mints one share of every outcome for the full payout
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+    let maker = get_user_details(&tx, "maker").await.unwrap();
+    assert_eq!(maker.balance, 4000);
+    assert_eq!(maker.holdings.get(&market_id).unwrap().stocks, vec![10, 10, 10]);
+
+    // every outcome trades in its own book
+    let carol = StockType(2);
+    limit_order(&tx, "maker", carol.clone(), 30, 5, &market_id, Ordertype::Sell).await.unwrap();
+    limit_order(&tx, "taker", carol.clone(), 30, 5, &market_id, Ordertype::Buy).await.unwrap();
+    assert_eq!(get_user_details(&tx, "taker").await.unwrap().holdings.get(&market_id).unwrap().stock(&carol), 5);
+    assert!(limit_order(&tx, "taker", StockType(3), 30, 1, &market_id, Ordertype::Buy).await.is_err());
+
+    // without a complement, buys of different outcomes never mint against each other
+    limit_order(&tx, "taker", StockType::StockA, 60, 2, &market_id, Ordertype::Buy).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockB, 50, 2, &market_id, Ordertype::Buy).await.unwrap();
+    let orderbook = get_orderbook(&tx, &market_id).await.unwrap();
+    assert_eq!(orderbook.outcomes, vec!["Alice", "Bob", "Carol"]);
+    assert_eq!(orderbook.books[0].buy.get(&60).unwrap()[0].quantity, 2);
+    assert_eq!(orderbook.books[1].buy.get(&50).unwrap()[0].quantity, 2);
+    let ticker = get_ticker(&tx, &market_id).await.unwrap();
+    assert_eq!(ticker.tickers[2].last_price, Some(30.0));
+    assert_eq!(ticker.tickers[0].last_price, None);
+
+    // a merge burns a full set, so it is limited by the scarcest outcome
+    assert!(merge_stocks(&tx, "maker", &market_id, 6).await.is_err());
+    merge_stocks(&tx, "maker", &market_id, 5).await.unwrap();
+    let maker = get_user_details(&tx, "maker").await.unwrap();
+    assert_eq!(maker.balance, 4000 + 150 - 100 + 500);
+    assert_eq!(maker.holdings.get(&market_id).unwrap().stocks, vec![5, 5, 0]);
+
+    // only the winning outcome pays out, resting orders are refunded
+    assert!(resolve_market(&tx, "maker", &market_id, WinningOutcome::Outcome(3)).await.is_err());
+    resolve_market(&tx, "maker", &market_id, WinningOutcome::Outcome(2)).await.unwrap();
+    assert_eq!(get_user_details(&tx, "taker").await.unwrap().balance, 5000 - 150 + 500);
+    assert_eq!(get_user_details(&tx, "maker").await.unwrap().balance, 4550 + 100);
+}
+
+#[tokio::test]
+async fn test_amm_liquidity() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "seller", "pass345").await.unwrap();
+    signup_user(&tx, "taker", "pass567").await.unwrap();
+
+    // the creator funds the worst case loss of 100 * b * ln 2 up front
+    assert!(new_market(&tx, "maker", "too deep", MarketOptions { liquidity: Some(100), ..Default::default() }).await.is_err());
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions { liquidity: Some(50), ..Default::default() }).await.unwrap();
+    assert_eq!(get_user_details(&tx, "maker").await.unwrap().balance, 5000 - 3466);
+    let ticker = get_ticker(&tx, &market_id).await.unwrap();
+    assert_eq!(ticker.tickers[0].amm_price, Some(50.0));
+    assert_eq!(ticker.tickers[1].amm_price, Some(50.0));
+
+    // an empty book is still tradable against the market maker
+    assert!(amm_trade(&tx, "taker", StockType::StockA, 10, &market_id, Ordertype::Buy, Some(500)).await.is_err());
+    let res = amm_trade(&tx, "taker", StockType::StockA, 10, &market_id, Ordertype::Buy, None).await.unwrap();
+    assert_eq!(res.amount, 525);
+    assert_eq!((res.trade.kind, res.trade.trade_qty, res.trade.from.as_str()), (TradeKind::Amm, 10, "market_maker"));
+    let taker = get_user_details(&tx, "taker").await.unwrap();
+    assert_eq!(taker.balance, 5000 - 525);
+    assert_eq!(taker.holdings.get(&market_id).unwrap().stock(&StockType::StockA), 10);
+    let ticker = get_ticker(&tx, &market_id).await.unwrap();
+    assert!(ticker.tickers[0].amm_price.unwrap() > 54.9);
+    assert_eq!(ticker.tickers[0].last_price, Some(52.5));     // the exact average, not the rounded trade price of 53
+
+    // market orders take the market maker while it is cheaper than the book, then the book
+    split_stocks(&tx, "seller", &market_id, 10).await.unwrap();
+    limit_order(&tx, "seller", StockType::StockA, 56, 5, &market_id, Ordertype::Sell).await.unwrap();
+    let res = quote(&tx, "taker", StockType::StockA, 10, &market_id, Ordertype::Buy, SlippageLimits::default()).await.unwrap();
+    assert_eq!(res.fills, vec![QuoteLevel { price: 56, quantity: 5 }]);
+    assert_eq!((res.filled, res.amm_filled), (10, 5));
+    assert_eq!((res.total_cost, res.amm_cost), (280 + 282, 282));
+    let res = market_order(&tx, "taker", StockType::StockA, 10, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest).await.unwrap();
+    assert_eq!(res.average_price, Some(56.2));
+    assert_eq!(res.trades.iter().map(|trade| trade.value).sum::<u64>(), 562);
+    let taker = get_user_details(&tx, "taker").await.unwrap();
+    assert_eq!(taker.balance, 5000 - 525 - 562);
+    assert_eq!(taker.holdings.get(&market_id).unwrap().stock(&StockType::StockA), 20);
+    assert_eq!(get_user_details(&tx, "seller").await.unwrap().balance, 4000 + 280);
+
+    // selling back pays the rest of the curve, rounded down
+    amm_trade(&tx, "taker", StockType::StockA, 5, &market_id, Ordertype::Sell, Some(281)).await.unwrap();
+    assert_eq!(get_user_details(&tx, "taker").await.unwrap().balance, 5000 - 525 - 562 + 281);
+
+    // on resolution the market maker's cash and inventory go back to the creator, no money is lost
+    resolve_market(&tx, "maker", &market_id, WinningOutcome::OutcomeA).await.unwrap();
+    let mut total = 0;
+    for username in ["maker", "seller", "taker"] {
+        total += get_user_details(&tx, username).await.unwrap().balance;
+    }
+    assert_eq!(get_user_details(&tx, "maker").await.unwrap().balance, 4526);
+    assert_eq!(total, 3 * 5000);
+}
+
+#[tokio::test]
+async fn test_amm_overflow() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions { liquidity: Some(50), ..Default::default() }).await.unwrap();
+
+    // quantities beyond i64 are priced without wrapping, so they cost more than anyone holds
+    let res = amm_trade(&tx, "taker", StockType::StockA, 1 << 63, &market_id, Ordertype::Buy, None).await;
+    assert!(matches!(res, Err(ExchangeError::InsufficientFunds { required: u64::MAX, .. })), "{:?}", res);
+    let res = amm_trade(&tx, "taker", StockType::StockA, u64::MAX, &market_id, Ordertype::Buy, None).await;
+    assert!(matches!(res, Err(ExchangeError::InsufficientFunds { .. })), "{:?}", res);
+    let res = market_order(&tx, "taker", StockType::StockA, 1 << 63, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest).await.unwrap();
+    assert!(res.filled > 0 && res.filled < 100);
+    let res = amm_trade(&tx, "taker", StockType::StockA, 1 << 63, &market_id, Ordertype::Sell, None).await;
+    assert!(matches!(res, Err(ExchangeError::InsufficientStock { .. })), "{:?}", res);
+    assert!(check_solvency(&tx).await.unwrap().solvent);
+}
+
+#[tokio::test]
+async fn test_error_responses() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "user1", "pass123").await.unwrap();
+    let market_id = new_market(&tx, "user1", "market_name", MarketOptions::default()).await.unwrap();
+
+    // every error carries a status, a stable code and its details
+    let err = split_stocks(&tx, "user1", &market_id, 100).await.unwrap_err();
+    assert_eq!(err, ExchangeError::InsufficientFunds { required: 10000, available: 5000 });
+    let res = err.error_response();
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = to_bytes(res.into_body()).await.unwrap();
+    let body : serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["code"], "INSUFFICIENT_FUNDS");
+    assert_eq!(body["message"], "Insufficient funds. Required: 10000, Available: 5000");
+    assert_eq!(body["details"], serde_json::json!({ "required": 10000, "available": 5000 }));
+
+    let err = limit_order(&tx, "user1", StockType(2), 50, 1, &market_id, Ordertype::Buy).await.unwrap_err();
+    assert_eq!(err, ExchangeError::UnknownOutcome { outcome: 2, outcomes: 2 });
+    assert_eq!(err.code(), "UNKNOWN_OUTCOME");
+    assert_eq!(signin_user(&tx, "user1", "wrong").await.unwrap_err().status_code(), StatusCode::UNAUTHORIZED);
+    assert_eq!(get_ticker(&tx, "missing").await.unwrap_err().status_code(), StatusCode::NOT_FOUND);
+    assert_eq!(signup_user(&tx, "user1", "pass123").await.unwrap_err().status_code(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn test_order_result() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 40, 2, &market_id, Ordertype::Sell).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 50, 2, &market_id, Ordertype::Sell).await.unwrap();
+
+    // a market order sweeping two levels reports its average price and every trade
+    let res = market_order(&tx, "taker", StockType::StockA, 5, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest).await.unwrap();
+    assert_eq!(res.status, OrderStatus::PartiallyFilledCancelled);
+    assert_eq!((res.filled, res.remaining, res.cancelled, res.refunded), (4, 0, 1, 0));
+    assert_eq!(res.average_price, Some(45.0));
+    let json = serde_json::to_value(&res).unwrap();
+    assert_eq!(json["status"], "PartiallyFilledCancelled");
+    let trade = &json["trades"][1];
+    assert_eq!((&trade["from"], &trade["to"], &trade["kind"]), (&serde_json::json!("maker"), &serde_json::json!("taker"), &serde_json::json!("Transfer")));
+    assert_eq!((&trade["trade_qty"], &trade["trade_price"], &trade["stock_type"]), (&serde_json::json!(2), &serde_json::json!(50), &serde_json::json!(0)));
+}
+
+#[tokio::test]
+async fn test_trade_records() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions { liquidity: Some(10), ..Default::default() }).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+    let ask = limit_order(&tx, "maker", StockType::StockA, 40, 2, &market_id, Ordertype::Sell).await.unwrap();
+    let bid = limit_order(&tx, "maker", StockType::StockB, 70, 2, &market_id, Ordertype::Buy).await.unwrap();
+
+    // every trade names its market, the resting and the incoming order and the side that took liquidity,
+    // the B bid at 70 mints at 30 and goes first
+    let buy = limit_order(&tx, "taker", StockType::StockA, 40, 4, &market_id, Ordertype::Buy).await.unwrap();
+    assert_eq!(buy.trades.len(), 2);
+    for (trade, maker_order_id) in buy.trades.iter().zip([&bid.order_id, &ask.order_id]) {
+        assert_eq!(trade.market_id, market_id);
+        assert_eq!(trade.maker_order_id.as_ref(), Some(maker_order_id));
+        assert_eq!(trade.taker_order_id.as_ref(), Some(&buy.order_id));
+        assert_eq!(trade.aggressor, Ordertype::Buy);
+    }
+    assert_eq!(buy.trades[0].kind, TradeKind::Mint);
+    assert_ne!(buy.trades[0].trade_id, buy.trades[1].trade_id);
+    assert!(buy.trades[0].timestamp <= buy.trades[1].timestamp);
+
+    // a market order routed to the market maker has no resting order on the other side
+    let sell = market_order(&tx, "taker", StockType::StockA, 4, &market_id, Ordertype::Sell, SelfTradePrevention::CancelNewest).await.unwrap();
+    let amm = sell.trades.iter().find(|trade| trade.kind == TradeKind::Amm).unwrap();
+    assert_eq!((amm.maker_order_id.as_ref(), amm.taker_order_id.as_ref()), (None, Some(&sell.order_id)));
+    assert_eq!(amm.aggressor, Ordertype::Sell);
+}
+
+#[tokio::test]
+async fn test_reservations() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions::default()).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+
+    // resting orders move funds and shares from available to reserved, under their order id
+    let bid = limit_order(&tx, "maker", StockType::StockB, 40, 5, &market_id, Ordertype::Buy).await.unwrap();
+    let ask = limit_order(&tx, "maker", StockType::StockA, 50, 6, &market_id, Ordertype::Sell).await.unwrap();
+    let maker = get_user_details(&tx, "maker").await.unwrap();
+    assert_eq!((maker.balance, maker.reserved_balance), (4000 - 40 * 5, 40 * 5));
+    let holdings = maker.holdings.get(&market_id).unwrap();
+    assert_eq!((holdings.stock(&StockType::StockA), holdings.reserved(&StockType::StockA)), (4, 6));
+    assert_eq!(maker.reservations.get(&bid.order_id).unwrap().balance, 40 * 5);
+    assert_eq!(maker.reservations.get(&ask.order_id).unwrap().stock, 6);
+
+    // fills take their part out of the reservation, a buy filling below its limit gets the rest back
+    let res = limit_order(&tx, "taker", StockType::StockA, 55, 4, &market_id, Ordertype::Buy).await.unwrap();
+    assert_eq!(res.filled, 4);
+    let taker = get_user_details(&tx, "taker").await.unwrap();
+    assert_eq!((taker.balance, taker.reserved_balance), (5000 - 50 * 4, 0));
+    assert!(taker.reservations.is_empty());
+    let maker = get_user_details(&tx, "maker").await.unwrap();
+    assert_eq!(maker.holdings.get(&market_id).unwrap().reserved(&StockType::StockA), 2);
+    assert_eq!(maker.reservations.get(&ask.order_id).unwrap().stock, 2);
+
+    // amending reserves only the difference, cancelling releases what is left
+    amend_order(&tx, "maker", &bid.order_id, Some(45), None).await.unwrap();
+    let maker = get_user_details(&tx, "maker").await.unwrap();
+    assert_eq!(maker.reserved_balance, 45 * 5);
+    cancel_order(&tx, "maker", &bid.order_id).await.unwrap();
+    let maker = get_user_details(&tx, "maker").await.unwrap();
+    assert_eq!((maker.balance, maker.reserved_balance), (4000 + 50 * 4, 0));
+    assert!(!maker.reservations.contains_key(&bid.order_id));
+
+    // settlement releases every open order before paying out
+    resolve_market(&tx, "maker", &market_id, WinningOutcome::OutcomeA).await.unwrap();
+    let maker = get_user_details(&tx, "maker").await.unwrap();
+    assert!(maker.reservations.is_empty());
+    assert_eq!((maker.balance, maker.reserved_balance), (4000 + 50 * 4 + 6 * 100, 0));
+}
+
+#[tokio::test]
+async fn test_order_cost_overflow() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "trader", "pass123").await.unwrap();
+    let market_id = new_market(&tx, "trader", "market_name", MarketOptions::default()).await.unwrap();
+
+    // the cost of these orders does not fit in u64, they are rejected before anything is locked
+    let res = limit_order(&tx, "trader", StockType::StockA, 50, u64::MAX / 25, &market_id, Ordertype::Buy).await;
+    assert!(matches!(res, Err(ExchangeError::Validation(_))), "{:?}", res);
+    let res = limit_order(&tx, "trader", StockType::StockA, 50, 368934881474191033, &market_id, Ordertype::Buy).await;
+    assert!(matches!(res, Err(ExchangeError::Validation(_))), "{:?}", res);
+    let res = stop_order(&tx, "trader", StockType::StockA, u64::MAX / 25, &market_id, Ordertype::Buy, 60, None).await;
+    assert!(matches!(res, Err(ExchangeError::Validation(_))), "{:?}", res);
+    let bid = limit_order(&tx, "trader", StockType::StockA, 40, 5, &market_id, Ordertype::Buy).await.unwrap();
+    let res = amend_order(&tx, "trader", &bid.order_id, None, Some(u64::MAX / 25)).await;
+    assert!(matches!(res, Err(ExchangeError::Validation(_))), "{:?}", res);
+
+    let trader = get_user_details(&tx, "trader").await.unwrap();
+    assert_eq!((trader.balance, trader.reserved_balance), (5000 - 40 * 5, 40 * 5));
+    assert!(check_solvency(&tx).await.unwrap().solvent);
+}
+
+// debits minus credits of one of the user's accounts, from their own entries
+fn ledger_balance(entries : &[JournalEntry] , account : &Account , asset : &Asset) -> i128 {
+    entries.iter().filter(|entry| entry.asset == *asset).map(|entry| {
+        (entry.debit == *account) as i128 * entry.amount as i128 - (entry.credit == *account) as i128 * entry.amount as i128
+    }).sum()
+}
+
+#[tokio::test]
+async fn test_ledger() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions { liquidity: Some(10), ..Default::default() }).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+
+    // every entry is a single debit and credit with a reason and what it belongs to
+    let entries = get_ledger(&tx, "maker").await.unwrap();
+    assert_eq!(entries[0].reason, Reason::SignupCredit);
+    assert_eq!((&entries[0].debit, &entries[0].credit, entries[0].amount), (&Account::Available("maker".to_string()), &Account::Funding, 5000));
+    assert_eq!(entries[1].reason, Reason::MarketSubsidy);
+    assert_eq!(entries[1].debit, Account::MarketMaker(market_id.clone()));
+    let split : Vec<&JournalEntry> = entries.iter().filter(|entry| entry.reason == Reason::Split).collect();
+    assert_eq!(split.len(), 3);
+    assert_eq!((&split[0].debit, split[0].amount), (&Account::Collateral(market_id.clone()), 1000));
+    assert!(split.iter().all(|entry| entry.reference.market_id.as_deref() == Some(market_id.as_str())));
+
+    // trades reference the trade and the order they filled, price improvement is released separately
+    limit_order(&tx, "maker", StockType::StockA, 40, 5, &market_id, Ordertype::Sell).await.unwrap();
+    let res = limit_order(&tx, "taker", StockType::StockA, 45, 5, &market_id, Ordertype::Buy).await.unwrap();
+    market_order(&tx, "taker", StockType::StockB, 3, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest).await.unwrap();
+    let entries = get_ledger(&tx, "taker").await.unwrap();
+    let trade_id = &res.trades[0].trade_id;
+    let trade = entries.iter().find(|entry| entry.reason == Reason::Trade && entry.reference.trade_id.as_ref() == Some(trade_id)).unwrap();
+    assert_eq!(trade.reference.order_id.as_ref(), Some(&res.order_id));
+    let improvement = entries.iter().find(|entry| entry.reason == Reason::PriceImprovement).unwrap();
+    assert_eq!((&improvement.debit, improvement.amount), (&Account::Available("taker".to_string()), 5 * 5));
+    assert!(entries.iter().any(|entry| entry.reason == Reason::AmmTrade && entry.credit == Account::MarketMaker(market_id.clone())));
+
+    // the entries add up to every balance the users see, before and after settlement
+    for settled in [false, true] {
+        if settled {
+            resolve_market(&tx, "maker", &market_id, WinningOutcome::OutcomeA).await.unwrap();
+        }
+        for username in ["maker", "taker"] {
+            let entries = get_ledger(&tx, username).await.unwrap();
+            let details = get_user_details(&tx, username).await.unwrap();
+            let (available, reserved) = (Account::Available(username.to_string()), Account::Reserved(username.to_string()));
+            assert_eq!(ledger_balance(&entries, &available, &Asset::Cash), details.balance as i128);
+            assert_eq!(ledger_balance(&entries, &reserved, &Asset::Cash), details.reserved_balance as i128);
+            let holdings = details.holdings.get(&market_id).cloned().unwrap_or_default();
+            for stock_type in [StockType::StockA, StockType::StockB] {
+                let shares = Asset::shares(&market_id, &stock_type);
+                assert_eq!(ledger_balance(&entries, &available, &shares), holdings.stock(&stock_type) as i128);
+                assert_eq!(ledger_balance(&entries, &reserved, &shares), holdings.reserved(&stock_type) as i128);
+            }
+        }
+    }
+    assert!(get_ledger(&tx, "nobody").await.is_err());
+}
+
+#[tokio::test]
+async fn test_solvency() {
+    let tx = spawn_background_worker();
+    let report = check_solvency(&tx).await.unwrap();
+    assert!(report.solvent);
+    assert_eq!(report.deposits, 0);
+
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions { liquidity: Some(10), ..Default::default() }).await.unwrap();
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 60, 4, &market_id, Ordertype::Sell).await.unwrap();
+    limit_order(&tx, "taker", StockType::StockB, 30, 6, &market_id, Ordertype::Buy).await.unwrap();
+    limit_order(&tx, "taker", StockType::StockA, 65, 2, &market_id, Ordertype::Buy).await.unwrap();
+    market_order(&tx, "taker", StockType::StockB, 5, &market_id, Ordertype::Buy, SelfTradePrevention::CancelNewest).await.unwrap();
+
+    // money only moves between users and markets, and open orders keep what they lock
+    let report = check_solvency(&tx).await.unwrap();
+    assert_eq!(report.violations, Vec::<String>::new());
+    assert_eq!(report.deposits, 10000);
+    assert_eq!(report.user_funds + report.market_funds, 10000);
+    assert!(report.market_funds >= 1000);
+
+    // settlement pays out every share, what the market keeps is accounted for too
+    resolve_market(&tx, "maker", &market_id, WinningOutcome::Neither).await.unwrap();
+    let report = check_solvency(&tx).await.unwrap();
+    assert!(report.solvent);
+    assert_eq!(report.user_funds + report.market_funds, 10000);
+}
+
+#[tokio::test]
+async fn test_deposits_and_withdrawals() {
+    let tx = spawn_worker(WorkerConfig { starting_balance: 0, funding: Some(Box::new(MockFunding::with_limit(2000))), ..WorkerConfig::default() });
+    signup_user(&tx, "alice", "pass123").await.unwrap();
+    assert_eq!(get_user_details(&tx, "alice").await.unwrap().balance, 0);
+
+    // deposits are credited once the funding source took the money
+    let res = deposit(&tx, "alice", 1500).await.unwrap();
+    assert_eq!((res.amount, res.balance), (1500, 1500));
+    assert!(matches!(deposit(&tx, "alice", 2500).await, Err(ExchangeError::FundingFailed(_))));
+    assert!(deposit(&tx, "alice", 0).await.is_err());
+    assert_eq!(deposit(&tx, "bob", 100).await, Err(ExchangeError::UserNotFound));
+    let entries = get_ledger(&tx, "alice").await.unwrap();
+    assert_eq!((&entries[0].credit, entries[0].reason), (&Account::Funding, Reason::Deposit));
+    assert_eq!(entries[0].reference.funding_id, Some(res.reference));
+
+    // a withdrawal holds its amount until it is decided
+    assert_eq!(withdraw(&tx, "alice", 2000).await, Err(ExchangeError::InsufficientFunds { required: 2000, available: 1500 }));
+    let first = withdraw(&tx, "alice", 400).await.unwrap();
+    let second = withdraw(&tx, "alice", 300).await.unwrap();
+    assert_eq!(first.status, WithdrawalStatus::Pending);
+    let details = get_user_details(&tx, "alice").await.unwrap();
+    assert_eq!((details.balance, details.pending_withdrawal), (800, 700));
+    assert_eq!(get_withdrawals(&tx, None).await.unwrap().len(), 2);
+
+    // approving pays out, rejecting gives the amount back, and either happens once
+    let approved = decide_withdrawal(&tx, &first.withdrawal_id, WithdrawalDecision::Approve).await.unwrap();
+    assert_eq!(approved.status, WithdrawalStatus::Approved);
+    assert!(approved.reference.is_some() && approved.decided_at.is_some());
+    assert_eq!(decide_withdrawal(&tx, &first.withdrawal_id, WithdrawalDecision::Reject).await, Err(ExchangeError::WithdrawalNotPending));
+    decide_withdrawal(&tx, &second.withdrawal_id, WithdrawalDecision::Reject).await.unwrap();
+    assert_eq!(decide_withdrawal(&tx, "missing", WithdrawalDecision::Approve).await, Err(ExchangeError::WithdrawalNotFound));
+    let details = get_user_details(&tx, "alice").await.unwrap();
+    assert_eq!((details.balance, details.pending_withdrawal), (1100, 0));
+    assert!(get_withdrawals(&tx, None).await.unwrap().is_empty());
+    let statuses : Vec<WithdrawalStatus> = get_withdrawals(&tx, Some("alice")).await.unwrap().iter().map(|withdrawal| withdrawal.status).collect();
+    assert_eq!(statuses, vec![WithdrawalStatus::Approved, WithdrawalStatus::Rejected]);
+
+    // only what was paid out left the exchange
+    let report = check_solvency(&tx).await.unwrap();
+    assert!(report.solvent);
+    assert_eq!(report.deposits, 1100);
+}
+
+#[tokio::test]
+async fn test_funding_limits() {
+    // without a funding source no money moves in or out
+    let tx = spawn_background_worker();
+    signup_user(&tx, "alice", "pass123").await.unwrap();
+    assert_eq!(deposit(&tx, "alice", 100).await, Err(ExchangeError::FundingUnavailable));
+    assert_eq!(withdraw(&tx, "alice", 100).await, Err(ExchangeError::FundingUnavailable));
+    assert_eq!(get_user_details(&tx, "alice").await.unwrap().balance, 5000);
+
+    // a deposit the balance can not hold is rejected before anything is credited
+    let tx = spawn_worker(WorkerConfig { starting_balance: 0, funding: Some(Box::new(MockFunding::default())), ..WorkerConfig::default() });
+    signup_user(&tx, "alice", "pass123").await.unwrap();
+    deposit(&tx, "alice", u64::MAX - 10).await.unwrap();
+    assert!(matches!(deposit(&tx, "alice", 100).await, Err(ExchangeError::Validation(_))));
+    assert_eq!(get_user_details(&tx, "alice").await.unwrap().balance, u64::MAX - 10);
+    assert!(check_solvency(&tx).await.unwrap().solvent);
+}
+
+#[tokio::test]
+async fn test_portfolio() {
+    let tx = spawn_background_worker();
+    signup_user(&tx, "maker", "pass123").await.unwrap();
+    signup_user(&tx, "taker", "pass345").await.unwrap();
+    let market_id = new_market(&tx, "maker", "market_name", MarketOptions::default()).await.unwrap();
+    assert!(get_portfolio(&tx, "maker").await.unwrap().positions.is_empty());
+
+    // a split costs the payout, shared evenly between the outcomes, and a merge realises nothing
+    split_stocks(&tx, "maker", &market_id, 10).await.unwrap();
+    merge_stocks(&tx, "maker", &market_id, 2).await.unwrap();
+    let portfolio = get_portfolio(&tx, "maker").await.unwrap();
+    assert_eq!(portfolio.positions.len(), 2);
+    assert_eq!((portfolio.positions[0].quantity, portfolio.positions[0].cost, portfolio.positions[0].average_price), (8, 400, Some(50.0)));
+    assert_eq!(portfolio.realised_pnl, 0);
+
+    // selling realises against the average cost, what is held is marked to the mid
+    limit_order(&tx, "maker", StockType::StockA, 60, 4, &market_id, Ordertype::Sell).await.unwrap();
+    limit_order(&tx, "taker", StockType::StockA, 60, 4, &market_id, Ordertype::Buy).await.unwrap();
+    limit_order(&tx, "taker", StockType::StockA, 50, 1, &market_id, Ordertype::Buy).await.unwrap();
+    limit_order(&tx, "maker", StockType::StockA, 70, 1, &market_id, Ordertype::Sell).await.unwrap();
+    let portfolio = get_portfolio(&tx, "maker").await.unwrap();
+    let (a, b) = (&portfolio.positions[0], &portfolio.positions[1]);
+    assert_eq!((a.outcome.as_str(), a.quantity, a.cost, a.realised_pnl), ("StockA", 4, 200, 40));
+    assert_eq!((a.mark_price, a.unrealised_pnl), (Some(60.0), Some(40.0)));
+    assert_eq!((b.mark_price, b.unrealised_pnl), (None, None));
+    assert_eq!((portfolio.realised_pnl, portfolio.unrealised_pnl), (40, 40.0));
+    let taker = get_portfolio(&tx, "taker").await.unwrap();
+    assert_eq!((taker.positions[0].quantity, taker.positions[0].average_price, taker.positions[0].unrealised_pnl), (4, Some(60.0), Some(0.0)));
+
+    // settlement closes every position at its payout and the PnL is kept
+    resolve_market(&tx, "maker", &market_id, WinningOutcome::OutcomeA).await.unwrap();
+    let portfolio = get_portfolio(&tx, "maker").await.unwrap();
+    assert!(portfolio.positions.iter().all(|position| position.quantity == 0 && position.cost == 0));
+    assert_eq!((portfolio.positions[0].realised_pnl, portfolio.positions[1].realised_pnl), (240, -400));
+    assert_eq!(portfolio.realised_pnl, get_user_details(&tx, "maker").await.unwrap().balance as i64 - 5000);
+    assert_eq!(get_portfolio(&tx, "taker").await.unwrap().realised_pnl, 160);
+}